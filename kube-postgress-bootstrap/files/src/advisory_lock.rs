@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::{timing::TimedClient, Error};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// the lock key of a user, prefixed so other applications' advisory locks on the
+// same server don't collide with it
+fn key(username: &str) -> String {
+    format!("kube-postgres-bootstrap:{}", username)
+}
+
+// takes the advisory lock of a user on the server, so runs that overlap (e.g. a
+// cronjob and a manual run) take turns on the same user instead of failing on
+// "role already exists". it's a session lock, a crashed run releases it with its
+// connection. pg_try_advisory_lock is polled since a blocking pg_advisory_lock
+// would hold up the other entries sharing the connection.
+pub async fn acquire(client: &tokio_postgres::Client, username: &str) -> Result<(), Error> {
+    let key = key(username);
+    let mut waiting = false;
+    loop {
+        let locked: bool = client
+            .timed_query_one("SELECT pg_try_advisory_lock(hashtext($1))", &[&key])
+            .await
+            .map_err(Error::db(username, "taking advisory lock"))?
+            .get(0);
+        if locked {
+            return Ok(());
+        }
+        if !waiting {
+            info!(
+                "user {} is locked by another bootstrap run, waiting for it",
+                username
+            );
+            waiting = true;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+pub async fn release(client: &tokio_postgres::Client, username: &str) {
+    if let Err(e) = client
+        .timed_query_one("SELECT pg_advisory_unlock(hashtext($1))", &[&key(username)])
+        .await
+    {
+        warn!(
+            "failed to release advisory lock of user {}: {}",
+            username, e
+        );
+    }
+}
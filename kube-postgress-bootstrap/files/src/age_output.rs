@@ -0,0 +1,92 @@
+use std::{collections::BTreeMap, env, io::Write, path::Path, str::FromStr};
+
+use anyhow::Context;
+use tracing::info;
+
+// writes each credential bundle as <AGE_OUTPUT_DIR>/<namespace>/<username>.json.age,
+// encrypted to the comma separated AGE_RECIPIENTS, for consumers that pull their
+// credentials from git instead of the kubernetes api
+pub fn write_bundle(
+    namespace: &str,
+    username: &str,
+    data: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let dir = match env::var("AGE_OUTPUT_DIR") {
+        Ok(dir) => dir,
+        Err(_) => return Ok(()),
+    };
+    let recipients = env::var("AGE_RECIPIENTS")
+        .context("AGE_RECIPIENTS must be set when AGE_OUTPUT_DIR is")?
+        .split(',')
+        .map(|recipient| {
+            age::x25519::Recipient::from_str(recipient.trim())
+                .map_err(|e| anyhow::anyhow!("invalid age recipient {}: {}", recipient, e))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut encrypted = Vec::new();
+    let encryptor = age::Encryptor::with_recipients(
+        recipients
+            .iter()
+            .map(|recipient| recipient as &dyn age::Recipient),
+    )?;
+    let armored =
+        age::armor::ArmoredWriter::wrap_output(&mut encrypted, age::armor::Format::AsciiArmor)?;
+    let mut writer = encryptor.wrap_output(armored)?;
+    writer.write_all(&serde_json::to_vec_pretty(data)?)?;
+    writer.finish()?.finish()?;
+
+    let path = Path::new(&dir)
+        .join(namespace)
+        .join(format!("{}.json.age", username));
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    std::fs::write(&path, encrypted)?;
+    info!("wrote encrypted credentials to {}", path.display());
+    Ok(())
+}
+
+// with AGE_OUTPUT_GIT_PUSH=true the output dir is treated as a git worktree and
+// the changed bundles are committed and pushed at the end of the run
+pub async fn commit_bundles() -> anyhow::Result<()> {
+    let dir = match env::var("AGE_OUTPUT_DIR") {
+        Ok(dir) if crate::env_flag("AGE_OUTPUT_GIT_PUSH") => dir,
+        _ => return Ok(()),
+    };
+
+    git(&dir, &["add", "--all"]).await?;
+    let changed = !tokio::process::Command::new("git")
+        .args(["diff", "--cached", "--quiet"])
+        .current_dir(&dir)
+        .status()
+        .await?
+        .success();
+    if !changed {
+        return Ok(());
+    }
+
+    git(
+        &dir,
+        &["commit", "--message", "Update database credentials"],
+    )
+    .await?;
+    git(&dir, &["push"]).await?;
+    info!("pushed encrypted credentials from {}", dir);
+    Ok(())
+}
+
+async fn git(dir: &str, args: &[&str]) -> anyhow::Result<()> {
+    let output = tokio::process::Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .await
+        .context("failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
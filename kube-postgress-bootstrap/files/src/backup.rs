@@ -0,0 +1,76 @@
+use std::{
+    env,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use tracing::info;
+
+use crate::{source, DBConnection};
+
+// dumps the database with pg_dump and uploads it to BACKUP_LOCATION before it is
+// dropped. returns the uploaded location, or None when backups are not configured.
+// an error means the backup failed and the drop must not go ahead.
+pub async fn backup_database(
+    db_connection_details: &DBConnection,
+    database: &str,
+) -> anyhow::Result<Option<String>> {
+    let location = match env::var("BACKUP_LOCATION") {
+        Ok(location) => location,
+        Err(_) => return Ok(None),
+    };
+    let (bucket, prefix) = source::split_s3_location(&location)?;
+
+    info!("backing up database {} before dropping it", database);
+    let mut command = tokio::process::Command::new("pg_dump");
+    command
+        .arg("--format=custom")
+        .env("PGHOST", &db_connection_details.host)
+        .env("PGPORT", &db_connection_details.port)
+        .env("PGUSER", &db_connection_details.username)
+        .env("PGPASSWORD", &db_connection_details.password)
+        .env("PGDATABASE", database);
+    // pg_dump connects with the same tls settings as the bootstrap
+    for (var, pg_var) in [
+        ("DB_SSLMODE", "PGSSLMODE"),
+        ("DB_SSLROOTCERT", "PGSSLROOTCERT"),
+        ("DB_SSLCERT", "PGSSLCERT"),
+        ("DB_SSLKEY", "PGSSLKEY"),
+    ] {
+        if let Ok(value) = env::var(var) {
+            command.env(pg_var, value);
+        }
+    }
+    let output = command.output().await.context("failed to run pg_dump")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "pg_dump of {} failed: {}",
+            database,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let key = format!(
+        "{}/{}-{}.dump",
+        prefix.trim_end_matches('/'),
+        database,
+        timestamp
+    )
+    .trim_start_matches('/')
+    .to_string();
+
+    source::s3_client()
+        .await
+        .put_object()
+        .bucket(bucket)
+        .key(&key)
+        .body(output.stdout.into())
+        .send()
+        .await
+        .with_context(|| format!("failed to upload backup of {}", database))?;
+
+    let artifact = format!("s3://{}/{}", bucket, key);
+    info!("audit: backed up database {} to {}", database, artifact);
+    Ok(Some(artifact))
+}
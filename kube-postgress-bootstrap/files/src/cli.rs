@@ -0,0 +1,362 @@
+use std::collections::BTreeMap;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::ConfigError;
+
+// every flag can also be set through the env var named next to it, which is how
+// the job spec configures them
+#[derive(Parser)]
+#[command(
+    name = "kube-postgres-bootstrap",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// config to bootstrap from when no subcommand is given
+    pub config: Option<String>,
+
+    /// namespace/name of a ConfigMap to bootstrap from when no config or
+    /// subcommand is given, every key holds a list of entries. subcommands take
+    /// configmap://namespace/name as their config.
+    #[arg(long, global = true, env = "CONFIG_MAP")]
+    pub configmap: Option<String>,
+
+    /// namespace/name of a secret with the username and password keys of the
+    /// admin user, instead of DB_USERNAME and DB_PASSWORD (or DB_PASSWORD_FILE)
+    #[arg(long, global = true, env = "ADMIN_SECRET")]
+    pub admin_secret: Option<String>,
+
+    /// format of the config, detected from the extension when not set
+    #[arg(long = "format", global = true, env = "CONFIG_FORMAT")]
+    pub config_format: Option<ConfigFormat>,
+
+    #[command(flatten)]
+    pub namespace_overrides: NamespaceOverrides,
+
+    /// kubeconfig to use instead of the in cluster config, for runs from a
+    /// laptop or ci runner. $KUBECONFIG and ~/.kube/config are used without it.
+    #[arg(long, global = true)]
+    pub kubeconfig: Option<String>,
+
+    /// kubeconfig context to use instead of the current one
+    #[arg(long, global = true, env = "KUBE_CONTEXT")]
+    pub context: Option<String>,
+
+    /// error, warn, info, debug or trace, debug also logs every sql statement and
+    /// kubernetes api call with its duration
+    #[arg(long, global = true, env = "LOG_LEVEL", default_value = "info", value_parser = ["error", "warn", "info", "debug", "trace"])]
+    pub log_level: String,
+
+    /// text or json, json writes one object per line for log pipelines
+    #[arg(long, global = true, env = "LOG_FORMAT", default_value = "text", value_parser = ["text", "json"])]
+    pub log_format: String,
+
+    /// attempts for database queries, connections and secret reads and writes
+    /// failing with a transient error
+    #[arg(long, global = true, env = "RETRY_MAX_ATTEMPTS", default_value_t = 5)]
+    pub retry_max_attempts: u32,
+
+    /// entries processed at once, entries for the same user still run one after
+    /// the other
+    #[arg(long, global = true, env = "PARALLELISM", default_value_t = 1)]
+    pub parallelism: usize,
+
+    /// name of the secrets of entries without a secretName, {username} and
+    /// {namespace} are filled in
+    #[arg(
+        long,
+        global = true,
+        env = "SECRET_NAME_TEMPLATE",
+        default_value = crate::DEFAULT_SECRET_NAME_TEMPLATE
+    )]
+    pub secret_name_template: String,
+
+    /// only reconcile these entries, comma separated usernames or
+    /// namespace/username. the others are reported as filtered out.
+    #[arg(long, global = true, env = "ONLY_ENTRIES")]
+    pub only: Option<String>,
+
+    /// only reconcile entries in namespaces matching this label selector, e.g.
+    /// team=payments
+    #[arg(long, global = true, env = "NAMESPACE_SELECTOR")]
+    pub namespace_selector: Option<String>,
+
+    /// after reconciling, drop the users of secrets created by the bootstrap whose
+    /// entry is gone and delete the secrets. secrets still referenced by a
+    /// workload are kept. PRUNE_SECRETS=true only deletes the secrets.
+    #[arg(long, global = true, env = "PRUNE")]
+    pub prune: bool,
+
+    /// also drop the databases of pruned users, backing them up first when
+    /// BACKUP_LOCATION is set
+    #[arg(long, global = true, env = "PRUNE_DATABASES", requires = "prune")]
+    pub prune_databases: bool,
+
+    /// write the secrets as manifests to <dir>/<namespace>/<name>.yaml instead of
+    /// creating them, for gitops repositories applied by e.g. argo cd. secrets
+    /// that exist are still read from the cluster.
+    #[arg(long, global = true, env = "MANIFESTS_DIR")]
+    pub manifests_dir: Option<String>,
+
+    /// what the manifests hold: secret, sealed-secret or external-secret
+    #[arg(
+        long,
+        global = true,
+        env = "MANIFESTS_FORMAT",
+        value_enum,
+        default_value = "secret"
+    )]
+    pub manifests_format: ManifestFormat,
+
+    /// the ClusterSecretStore external-secret manifests pull from, the
+    /// credentials have to be written to it with secretStores
+    #[arg(long, global = true, env = "MANIFESTS_SECRET_STORE")]
+    pub manifests_secret_store: Option<String>,
+
+    /// namespace/name of a configmap recording the users, databases and secrets
+    /// the bootstrap manages, with when they were created and last reconciled
+    #[arg(long, global = true, env = "INVENTORY_CONFIGMAP")]
+    pub inventory: Option<String>,
+
+    /// don't emit kubernetes events for created users and secrets, rotations and
+    /// failures. they're also stopped when creating events is forbidden.
+    #[arg(long, global = true, env = "DISABLE_EVENTS")]
+    pub disable_events: bool,
+
+    /// serves prometheus metrics at /metrics and the /healthz and /readyz probes
+    /// on this address (e.g. 0.0.0.0:9090), in bootstrap --watch and the git,
+    /// watch-namespaces and operator modes
+    #[arg(long, global = true, env = "LISTEN_ADDR")]
+    pub listen_addr: Option<String>,
+
+    /// only reconcile while holding a lease, for more than one replica of
+    /// bootstrap --watch or the git, watch-namespaces or operator modes
+    #[arg(long, global = true, env = "LEADER_ELECTION")]
+    pub leader_election: bool,
+
+    /// name of the coordination.k8s.io lease replicas compete for, in
+    /// POD_NAMESPACE or the current namespace
+    #[arg(
+        long,
+        global = true,
+        env = "LEADER_ELECTION_LEASE",
+        default_value = "kube-postgres-bootstrap"
+    )]
+    pub leader_election_lease: String,
+
+    /// total time a single operation keeps retrying for
+    #[arg(
+        long,
+        global = true,
+        env = "RETRY_DEADLINE_SECONDS",
+        default_value_t = 60
+    )]
+    pub retry_deadline_seconds: u64,
+
+    /// fails a single sql statement or kubernetes api call taking longer, 0
+    /// waits forever
+    #[arg(
+        long,
+        global = true,
+        env = "OPERATION_TIMEOUT_SECONDS",
+        default_value_t = 300
+    )]
+    pub operation_timeout_seconds: u64,
+
+    /// statement_timeout of every postgres session, 0 leaves the server default
+    #[arg(
+        long,
+        global = true,
+        env = "STATEMENT_TIMEOUT_SECONDS",
+        default_value_t = 120
+    )]
+    pub statement_timeout_seconds: u64,
+
+    /// lock_timeout of every postgres session and lock_wait_timeout of mysql
+    /// ones, so ddl queued behind another session fails instead of hanging. 0
+    /// leaves the server default.
+    #[arg(
+        long,
+        global = true,
+        env = "LOCK_TIMEOUT_SECONDS",
+        default_value_t = 30
+    )]
+    pub lock_timeout_seconds: u64,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// creates the users, databases and secrets of every entry
+    #[command(alias = "apply")]
+    Bootstrap {
+        /// local path or directory, s3://, https:// or oci:// location
+        config: String,
+        /// only print the changes and sql that would be made, see plan
+        #[arg(long, env = "DRY_RUN")]
+        dry_run: bool,
+        /// output of --dry-run: text, markdown or json
+        #[arg(long, env = "PLAN_OUTPUT", default_value = "text")]
+        output: String,
+        /// repair entries whose secret exists instead of skipping them: recreate
+        /// missing users, databases and grants, reset drifted passwords to the
+        /// one in the secret and re-render the secret
+        #[arg(long, env = "REPAIR_DRIFT")]
+        repair: bool,
+        /// keep running and reconcile again when the config changes, on SIGHUP
+        /// and every WATCH_RESYNC_SECONDS
+        #[arg(long, env = "WATCH_CONFIG")]
+        watch: bool,
+    },
+    /// generates new passwords for every entry, or only the targeted ones
+    Rotate {
+        /// optional for targeted rotations, which find entries from the secrets
+        config: Option<String>,
+        #[command(flatten)]
+        target: RotationArgs,
+    },
+    /// checks that the credentials in every secret work against the database
+    Verify {
+        config: String,
+        /// reset drifted passwords to the one in the secret
+        #[arg(long)]
+        repair_auth: bool,
+    },
+    /// prints the changes bootstrapping would make
+    Plan {
+        config: String,
+        /// text, markdown or json
+        #[arg(long, env = "PLAN_OUTPUT", default_value = "text")]
+        output: String,
+    },
+    /// drops a user and deletes its secret
+    Delete {
+        /// the entry's databases are taken from the config when given
+        config: Option<String>,
+        #[arg(long)]
+        user: String,
+        #[arg(long)]
+        namespace: String,
+        /// also drop the entry's databases, backing them up first when
+        /// BACKUP_LOCATION is set
+        #[arg(long)]
+        drop_databases: bool,
+        #[arg(long, env = "DRY_RUN")]
+        dry_run: bool,
+    },
+    /// checks the config against the schema and its entries for mistakes without
+    /// connecting to the database, exiting non-zero with every error found
+    Validate {
+        config: Option<String>,
+        /// print the json schema of the config instead, e.g. for editors
+        #[arg(long)]
+        print_schema: bool,
+    },
+    /// prints a config describing the users, databases and secrets that already
+    /// exist, for adopting the bootstrap on a cluster set up by hand
+    Export {
+        /// namespace of users without a secret created by the bootstrap
+        #[arg(long, default_value = "default")]
+        namespace: String,
+        #[arg(long, value_enum, default_value = "yaml")]
+        output: ConfigFormat,
+    },
+    /// per-branch preview environments: up, down or gc
+    Preview { action: String },
+    /// reconciles from a config file in a git repository, configured with GIT_* env vars
+    Git,
+    /// provisions namespaces annotated with postgres-bootstrap/databases
+    WatchNamespaces,
+    /// runs as a controller for DatabaseBootstrap resources
+    Operator {
+        /// prints the DatabaseBootstrap CustomResourceDefinition to apply before
+        /// starting the controller, instead of running it
+        #[arg(long)]
+        print_crd: bool,
+    },
+}
+
+#[derive(Args)]
+pub struct RotationArgs {
+    #[arg(long, env = "ROTATE_USER")]
+    pub user: Option<String>,
+    #[arg(long, env = "ROTATE_NAMESPACE")]
+    pub namespace: Option<String>,
+    #[arg(long, env = "ROTATE_ALL_IN_CLUSTER")]
+    pub all_in_cluster: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ManifestFormat {
+    /// the plain Secret, only for repositories nobody else can read
+    Secret,
+    /// encrypted by kubeseal, with the controller's certificate from
+    /// SEALED_SECRETS_CERT or fetched from the cluster
+    SealedSecret,
+    /// an ExternalSecret extracting <namespace>/<username> from the store
+    ExternalSecret,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ConfigFormat {
+    Json,
+    Yaml,
+}
+
+impl Command {
+    // the modes that keep running, which serve LISTEN_ADDR, take part in leader
+    // election and reload on SIGHUP
+    pub fn is_long_running(&self) -> bool {
+        matches!(
+            self,
+            Command::Bootstrap {
+                watch: true,
+                dry_run: false,
+                ..
+            } | Command::Git
+                | Command::WatchNamespaces
+                | Command::Operator { print_crd: false }
+        )
+    }
+}
+
+impl ConfigFormat {
+    // .yaml and .yml locations (optionally gzipped) are yaml, everything else json
+    pub fn detect(location: &str) -> ConfigFormat {
+        let location = location.trim_end_matches(".gz");
+        if location.ends_with(".yaml") || location.ends_with(".yml") {
+            ConfigFormat::Yaml
+        } else {
+            ConfigFormat::Json
+        }
+    }
+}
+
+// --namespace-map=from:to,... remaps individual namespaces, --target-namespace then
+// forces every entry into a single namespace (e.g. to try a prod config on staging)
+#[derive(Args, Clone, Default)]
+pub struct NamespaceOverrides {
+    #[arg(long, global = true, env = "NAMESPACE_MAP")]
+    pub namespace_map: Option<String>,
+    #[arg(long, global = true, env = "TARGET_NAMESPACE")]
+    pub target_namespace: Option<String>,
+}
+
+impl NamespaceOverrides {
+    pub fn namespace_map(&self) -> Result<BTreeMap<String, String>, ConfigError> {
+        self.namespace_map
+            .iter()
+            .flat_map(|mapping| mapping.split(','))
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.split_once(':') {
+                Some((from, to)) => Ok((from.trim().to_string(), to.trim().to_string())),
+                None => Err(ConfigError::setting(
+                    "NAMESPACE_MAP",
+                    format!("{:?} is not formatted as from:to", pair),
+                )),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    normalize_host, provisioner, provisioner::Provisioner, quote_identifier, quote_literal,
+    secret_values, timing::TimedClient, try_connect, DBConnection, DatabaseConfig, Error,
+};
+
+const DEFAULT_PORT: u16 = 26257;
+
+// a cockroachdb cluster, for entries with engine: cockroachdb. it's reached over
+// the postgres protocol on the entry's host, or DB_HOST without one, but roles
+// are checked in system.users and databases in crdb_internal since pg_roles and
+// pg_database are only partially emulated. usernames are always lower case.
+pub(crate) struct Cockroach {
+    client: tokio_postgres::Client,
+    details: DBConnection,
+    entry: String,
+}
+
+impl Cockroach {
+    pub async fn connect(
+        kube_client: &kube::Client,
+        default: &DBConnection,
+        db_config: &DatabaseConfig,
+    ) -> Result<Cockroach, Error> {
+        let (host, port) = match &db_config.host {
+            Some(host) => (
+                normalize_host(host),
+                db_config.port.unwrap_or(DEFAULT_PORT).to_string(),
+            ),
+            None => (
+                default.host.clone(),
+                db_config
+                    .port
+                    .map(|port| port.to_string())
+                    .unwrap_or_else(|| default.port.clone()),
+            ),
+        };
+        let (username, password) =
+            provisioner::admin_credentials(kube_client, default, db_config).await?;
+        let details = DBConnection {
+            host,
+            port,
+            username,
+            password,
+        };
+
+        let entry = db_config.username.clone();
+        let client = try_connect(&details.connection_string())
+            .await
+            .map_err(Error::db(
+                &entry,
+                &format!("connecting to {}:{}", details.host, details.port),
+            ))?;
+        Ok(Cockroach {
+            client,
+            details,
+            entry,
+        })
+    }
+
+    async fn execute(&self, stage: &str, statement: String) -> Result<(), Error> {
+        self.client
+            .timed_execute(&statement, &[])
+            .await
+            .map_err(Error::db(&self.entry, stage))?;
+        Ok(())
+    }
+
+    async fn exists(&self, stage: &str, query: &str, name: &str) -> Result<bool, Error> {
+        let rows = self
+            .client
+            .timed_query(query, &[&name])
+            .await
+            .map_err(Error::db(&self.entry, stage))?;
+        Ok(!rows.is_empty())
+    }
+}
+
+impl Provisioner for Cockroach {
+    async fn user_exists(&mut self, username: &str) -> Result<bool, Error> {
+        self.exists(
+            "checking user",
+            "SELECT 1 FROM system.users WHERE username = lower($1)",
+            username,
+        )
+        .await
+    }
+
+    async fn create_user(&mut self, username: &str, password: &str) -> Result<(), Error> {
+        self.execute(
+            "creating user",
+            format!(
+                "CREATE USER {} WITH LOGIN PASSWORD {}",
+                quote_identifier(username),
+                quote_literal(password)
+            ),
+        )
+        .await
+    }
+
+    async fn set_password(&mut self, username: &str, password: &str) -> Result<(), Error> {
+        self.execute(
+            "setting password",
+            format!(
+                "ALTER USER {} WITH PASSWORD {}",
+                quote_identifier(username),
+                quote_literal(password)
+            ),
+        )
+        .await
+    }
+
+    // a user with privileges or owned objects can't be dropped, DROP OWNED BY
+    // takes care of both but fails for a missing user
+    async fn drop_user(&mut self, username: &str) -> Result<(), Error> {
+        if !self.user_exists(username).await? {
+            return Ok(());
+        }
+        self.execute(
+            "dropping owned objects",
+            format!("DROP OWNED BY {}", quote_identifier(username)),
+        )
+        .await?;
+        self.execute(
+            "dropping user",
+            format!("DROP USER IF EXISTS {}", quote_identifier(username)),
+        )
+        .await
+    }
+
+    async fn ensure_database(&mut self, database: &str) -> Result<bool, Error> {
+        let stage = format!("creating database {}", database);
+        if self
+            .exists(
+                &stage,
+                "SELECT 1 FROM crdb_internal.databases WHERE name = $1",
+                database,
+            )
+            .await?
+        {
+            return Ok(false);
+        }
+        self.execute(
+            &stage,
+            format!(
+                "CREATE DATABASE IF NOT EXISTS {}",
+                quote_identifier(database)
+            ),
+        )
+        .await?;
+        Ok(true)
+    }
+
+    // non empty databases are only dropped with CASCADE
+    async fn drop_database(&mut self, database: &str) -> Result<(), Error> {
+        self.execute(
+            &format!("dropping database {}", database),
+            format!(
+                "DROP DATABASE IF EXISTS {} CASCADE",
+                quote_identifier(database)
+            ),
+        )
+        .await
+    }
+
+    // database privileges don't extend to the tables in it, the user owns the
+    // tables it creates and gets the public schema to create them in
+    async fn grant(
+        &mut self,
+        username: &str,
+        database: &str,
+        privileges: Option<&[String]>,
+    ) -> Result<(), Error> {
+        let stage = format!("granting access to {}", database);
+        let privileges = privileges
+            .map(|privileges| privileges.join(", "))
+            .unwrap_or_else(|| "ALL".to_string());
+        self.execute(
+            &stage,
+            format!(
+                "GRANT {} ON DATABASE {} TO {}",
+                privileges,
+                quote_identifier(database),
+                quote_identifier(username)
+            ),
+        )
+        .await?;
+        self.execute(
+            &stage,
+            format!(
+                "GRANT USAGE, CREATE ON SCHEMA {}.public TO {}",
+                quote_identifier(database),
+                quote_identifier(username)
+            ),
+        )
+        .await
+    }
+
+    // the same keys as for postgres, postgres:// uris work for cockroachdb
+    fn secret_values(
+        &self,
+        db_config: &DatabaseConfig,
+        password: &str,
+    ) -> BTreeMap<String, String> {
+        secret_values(&self.details, db_config, password)
+    }
+
+    async fn disconnect(self) {}
+}
@@ -0,0 +1,54 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{env_number, ConfigError};
+
+// entries reconciled at once, set from --parallelism
+static PARALLELISM: AtomicUsize = AtomicUsize::new(1);
+static SERVER_LIMIT: OnceLock<Option<usize>> = OnceLock::new();
+
+// set from --parallelism and MAX_CONCURRENCY_PER_SERVER before anything runs
+pub fn configure(parallelism: usize) -> Result<(), ConfigError> {
+    PARALLELISM.store(parallelism.max(1), Ordering::Relaxed);
+    let limit = env_number("MAX_CONCURRENCY_PER_SERVER")?;
+    if limit == Some(0) {
+        // every entry would wait for a permit forever
+        return Err(ConfigError::setting(
+            "MAX_CONCURRENCY_PER_SERVER",
+            "must be at least 1",
+        ));
+    }
+    let _ = SERVER_LIMIT.set(limit);
+    Ok(())
+}
+
+pub fn parallelism() -> usize {
+    PARALLELISM.load(Ordering::Relaxed)
+}
+
+// one semaphore per postgres server, keyed by host:port
+static SERVER_LIMITS: Mutex<BTreeMap<String, Arc<Semaphore>>> = Mutex::new(BTreeMap::new());
+
+// MAX_CONCURRENCY_PER_SERVER caps how many entries are processed against the same
+// server at once, independent of the overall concurrency, as every entry opens its
+// own database sessions and a busy server would run out of max_connections.
+// returns None when no cap is configured. the semaphores are never closed, a
+// closed one would only lift the cap.
+pub async fn server_permit(host: &str, port: &str) -> Option<OwnedSemaphorePermit> {
+    let limit = SERVER_LIMIT.get().copied().flatten()?;
+
+    let semaphore = SERVER_LIMITS
+        .lock()
+        .unwrap()
+        .entry(format!("{}:{}", host, port))
+        .or_insert_with(|| Arc::new(Semaphore::new(limit)))
+        .clone();
+    semaphore.acquire_owned().await.ok()
+}
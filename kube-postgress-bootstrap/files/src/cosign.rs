@@ -0,0 +1,34 @@
+use std::env;
+
+use anyhow::Context;
+use base64::Engine;
+use p256::{
+    ecdsa::{signature::Verifier, Signature, VerifyingKey},
+    pkcs8::DecodePublicKey,
+};
+
+use crate::source;
+
+// COSIGN_PUBLIC_KEY is the location of a cosign.pub, config is only applied when
+// it's signed by the matching key
+pub async fn public_key() -> anyhow::Result<Option<VerifyingKey>> {
+    let location = match env::var("COSIGN_PUBLIC_KEY") {
+        Ok(location) => location,
+        Err(_) => return Ok(None),
+    };
+    let pem = source::read_source(&location).await?;
+    let key = VerifyingKey::from_public_key_pem(&pem)
+        .with_context(|| format!("{} is not an ecdsa p-256 public key", location))?;
+    Ok(Some(key))
+}
+
+// checks a base64 encoded signature as written by `cosign sign-blob`
+pub fn verify(public_key: &VerifyingKey, content: &[u8], signature: &[u8]) -> anyhow::Result<()> {
+    let signature = base64::engine::general_purpose::STANDARD
+        .decode(signature.trim_ascii())
+        .context("cosign signature is not base64")?;
+    let signature = Signature::from_der(&signature).context("invalid cosign signature")?;
+    public_key
+        .verify(content, &signature)
+        .context("cosign signature verification failed")
+}
@@ -0,0 +1,135 @@
+use std::{collections::BTreeMap, env};
+
+use anyhow::Context;
+use kube::{
+    api::{Api, ApiResource, DynamicObject, GroupVersionKind, Patch, PatchParams},
+    error::ErrorResponse,
+};
+use tracing::info;
+
+use crate::{store, MANAGED_BY, MANAGED_BY_LABEL};
+
+// instead of creating a kubernetes secret the credentials are pushed to an external
+// store and a SecretProviderClass is created for the secrets store csi driver
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CsiConfig {
+    provider: CsiProvider,
+    // vault kv path (within mount) or aws secrets manager secret name
+    path: String,
+    #[serde(default = "default_vault_mount")]
+    mount: String,
+    // vault role the csi provider authenticates with
+    role: Option<String>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum CsiProvider {
+    Vault,
+    Aws,
+}
+
+fn default_vault_mount() -> String {
+    "secret".to_string()
+}
+
+fn secret_provider_class_api(kube_client: &kube::Client, namespace: &str) -> Api<DynamicObject> {
+    let gvk = GroupVersionKind::gvk("secrets-store.csi.x-k8s.io", "v1", "SecretProviderClass");
+    Api::namespaced_with(
+        kube_client.clone(),
+        namespace,
+        &ApiResource::from_gvk_with_plural(&gvk, "secretproviderclasses"),
+    )
+}
+
+pub async fn secret_provider_class_exists(
+    kube_client: &kube::Client,
+    namespace: &str,
+    name: &str,
+) -> anyhow::Result<bool> {
+    match secret_provider_class_api(kube_client, namespace)
+        .get(name)
+        .await
+    {
+        Ok(_) => Ok(true),
+        Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn publish(
+    kube_client: &kube::Client,
+    namespace: &str,
+    name: &str,
+    csi: &CsiConfig,
+    data: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    // the objects parameter is parsed as yaml by both providers, json is valid yaml
+    let (provider, parameters) = match csi.provider {
+        CsiProvider::Vault => {
+            store::write_vault_kv(&csi.mount, &csi.path, data).await?;
+            let objects: Vec<serde_json::Value> = data
+                .keys()
+                .map(|key| {
+                    serde_json::json!({
+                        "objectName": key,
+                        "secretPath": format!("{}/data/{}", csi.mount, csi.path),
+                        "secretKey": key,
+                    })
+                })
+                .collect();
+            let parameters = serde_json::json!({
+                "vaultAddress": env::var("VAULT_ADDR")?,
+                "roleName": csi.role.as_ref().context("csi role is required for the vault provider")?,
+                "objects": serde_json::to_string(&objects)?,
+            });
+            ("vault", parameters)
+        }
+        CsiProvider::Aws => {
+            store::write_aws_secret(&csi.path, data).await?;
+            let jmes_path: Vec<serde_json::Value> = data
+                .keys()
+                .map(
+                    |key| serde_json::json!({ "path": format!("\"{}\"", key), "objectAlias": key }),
+                )
+                .collect();
+            let objects = serde_json::json!([{
+                "objectName": csi.path,
+                "objectType": "secretsmanager",
+                "jmesPath": jmes_path,
+            }]);
+            let parameters = serde_json::json!({
+                "objects": serde_json::to_string(&objects)?,
+            });
+            ("aws", parameters)
+        }
+    };
+
+    let secret_provider_class = serde_json::json!({
+        "apiVersion": "secrets-store.csi.x-k8s.io/v1",
+        "kind": "SecretProviderClass",
+        "metadata": {
+            "name": name,
+            "namespace": namespace,
+            "labels": { MANAGED_BY_LABEL: MANAGED_BY },
+        },
+        "spec": {
+            "provider": provider,
+            "parameters": parameters,
+        },
+    });
+    secret_provider_class_api(kube_client, namespace)
+        .patch(
+            name,
+            &PatchParams::apply(MANAGED_BY),
+            &Patch::Apply(&secret_provider_class),
+        )
+        .await?;
+
+    info!(
+        "successfully created secret provider class with db creds: {}",
+        name
+    );
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::{Api, DeleteParams},
+    error::ErrorResponse,
+};
+use tracing::info;
+
+use crate::{
+    backup, inventory, quote_identifier, secret_name, timing::TimedClient, DBClients, DBConnection,
+    DatabaseConfig,
+};
+
+// drops the entry's user and deletes its secret. objects the user owns in its
+// databases are reassigned to the admin user so dropping the role can't lose data,
+// unless the databases themselves are dropped too.
+pub async fn delete(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    db_config: &DatabaseConfig,
+    drop_databases: bool,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let username = db_config.username.as_str();
+    let secret_name = secret_name(db_config);
+    if dry_run {
+        if drop_databases {
+            for db in db_config.databases.iter() {
+                info!("would drop database {}", db);
+            }
+        }
+        info!("would drop user {}", username);
+        for namespace in std::iter::once(&db_config.namespace).chain(&db_config.extra_namespaces) {
+            info!("would delete secret {}/{}", namespace, secret_name);
+        }
+        return Ok(());
+    }
+
+    for db in db_config.databases.iter() {
+        let db_exists = clients
+            .admin
+            .timed_query("SELECT 1 FROM pg_database WHERE datname = $1;", &[db])
+            .await?;
+        if db_exists.is_empty() {
+            continue;
+        }
+
+        if drop_databases {
+            backup::backup_database(
+                clients.privileged_connection_details(db_connection_details),
+                db,
+            )
+            .await?;
+            info!("dropping database {}", db);
+            clients
+                .privileged()
+                .timed_execute(
+                    "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname = $1 AND pid <> pg_backend_pid();",
+                    &[db],
+                )
+                .await?;
+            clients
+                .admin
+                .timed_execute(
+                    format!("DROP DATABASE IF EXISTS {}", quote_identifier(db)).as_str(),
+                    &[],
+                )
+                .await?;
+        } else {
+            info!(
+                "reassigning objects of user {} in database {}",
+                username, db
+            );
+            let db_client = clients
+                .privileged_database_client(db_connection_details, db)
+                .await?;
+            for statement in [
+                format!(
+                    "REASSIGN OWNED BY {} TO CURRENT_USER",
+                    quote_identifier(username)
+                ),
+                format!("DROP OWNED BY {}", quote_identifier(username)),
+            ] {
+                db_client.timed_execute(statement.as_str(), &[]).await?;
+            }
+        }
+    }
+
+    info!("dropping user {}", username);
+    clients
+        .admin
+        .timed_execute(
+            format!("DROP ROLE IF EXISTS {}", quote_identifier(username)).as_str(),
+            &[],
+        )
+        .await?;
+
+    // copies in extraNamespaces are only known when the entry comes from a config,
+    // otherwise they're left to pruning
+    for namespace in std::iter::once(&db_config.namespace).chain(&db_config.extra_namespaces) {
+        let secrets: Api<Secret> = Api::namespaced(kube_client.clone(), namespace);
+        match secrets.delete(&secret_name, &DeleteParams::default()).await {
+            Ok(_) | Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    inventory::remove(kube_client, &db_config.namespace, username).await?;
+
+    info!(
+        "successfully deleted user {} and secret {}/{}",
+        username, db_config.namespace, secret_name
+    );
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use tokio_postgres::error::SqlState;
+
+pub use crate::timeouts::OperationTimeout;
+
+// errors returned by the library, split by what failed so embedding services can
+// tell a bad config apart from an unreachable database or api server
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Db(#[from] DbError),
+    // entries provisioned on mysql or mariadb
+    #[error(transparent)]
+    MySql(#[from] MySqlError),
+    #[error(transparent)]
+    Kube(#[from] KubeError),
+    #[error("{entry} did not finish within its {seconds}s timeout")]
+    Timeout { entry: String, seconds: u64 },
+    // a single sql statement running into --operation-timeout-seconds
+    #[error("timeout for {entry} while {stage}: {source}")]
+    OperationTimeout {
+        entry: String,
+        stage: String,
+        #[source]
+        source: OperationTimeout,
+    },
+    #[error("{failed} of {total} entries failed")]
+    Failed { failed: usize, total: usize },
+    #[error("{host} is a standby, point DB_HOST at the primary or list all hosts comma separated to have the primary picked")]
+    Standby { host: String },
+    // publishing credentials to an external store (1password, vault, aws, age)
+    #[error("failed to publish credentials for {entry} to {target}: {source:#}")]
+    Output {
+        entry: String,
+        target: String,
+        source: anyhow::Error,
+    },
+    // failures in the standalone modes (preview, verify, plan, git polling)
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read config {location}: {source:#}")]
+    Read {
+        location: String,
+        source: anyhow::Error,
+    },
+    #[error("failed to decrypt config: {0:#}")]
+    Decrypt(anyhow::Error),
+    #[error("failed to parse config: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("failed to parse yaml config: {0}")]
+    ParseYaml(#[from] serde_yaml::Error),
+    #[error("invalid config for {entry}: {message}")]
+    Invalid { entry: String, message: String },
+    // the environment variables and flags the run is configured with
+    #[error("invalid {name}: {message}")]
+    Setting { name: String, message: String },
+    // every mismatch with the schema, as <entry>.<field path>: <problem>
+    #[error("config doesn't match the schema:\n  {}", .0.join("\n  "))]
+    Schema(Vec<String>),
+}
+
+// entry is the username of the config entry, or "admin" for the bootstrap's own
+// connections, stage the step that failed (e.g. "creating user")
+#[derive(Debug, thiserror::Error)]
+#[error("database error for {entry} while {stage}: {source}")]
+pub struct DbError {
+    pub entry: String,
+    pub stage: String,
+    #[source]
+    pub source: tokio_postgres::Error,
+}
+
+// what the timed client methods fail with: the server's error, or the statement
+// being given up on after the operation timeout
+#[derive(Debug, thiserror::Error)]
+pub enum SqlError {
+    #[error(transparent)]
+    Postgres(#[from] tokio_postgres::Error),
+    #[error(transparent)]
+    Timeout(#[from] OperationTimeout),
+}
+
+impl SqlError {
+    pub fn code(&self) -> Option<&SqlState> {
+        match self {
+            SqlError::Postgres(e) => e.code(),
+            SqlError::Timeout(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("mysql error for {entry} while {stage}: {source}")]
+pub struct MySqlError {
+    pub entry: String,
+    pub stage: String,
+    #[source]
+    pub source: mysql_async::Error,
+}
+
+// resource is kind namespace/name of the object the request was for
+#[derive(Debug, thiserror::Error)]
+#[error("kubernetes api error for {resource}: {source}")]
+pub struct KubeError {
+    pub resource: String,
+    #[source]
+    pub source: kube::Error,
+}
+
+impl ConfigError {
+    pub(crate) fn setting(name: &str, message: impl Into<String>) -> ConfigError {
+        ConfigError::Setting {
+            name: name.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Error {
+    pub(crate) fn db<E: Into<SqlError>>(entry: &str, stage: &str) -> impl FnOnce(E) -> Error {
+        let (entry, stage) = (entry.to_string(), stage.to_string());
+        move |source| match source.into() {
+            SqlError::Postgres(source) => Error::Db(DbError {
+                entry,
+                stage,
+                source,
+            }),
+            SqlError::Timeout(source) => Error::OperationTimeout {
+                entry,
+                stage,
+                source,
+            },
+        }
+    }
+
+    pub(crate) fn mysql(entry: &str, stage: &str) -> impl FnOnce(mysql_async::Error) -> Error {
+        let (entry, stage) = (entry.to_string(), stage.to_string());
+        move |source| {
+            Error::MySql(MySqlError {
+                entry,
+                stage,
+                source,
+            })
+        }
+    }
+
+    pub(crate) fn kube(resource: String) -> impl FnOnce(kube::Error) -> Error {
+        move |source| Error::Kube(KubeError { resource, source })
+    }
+
+    pub(crate) fn output(entry: &str, target: &str) -> impl FnOnce(anyhow::Error) -> Error {
+        let (entry, target) = (entry.to_string(), target.to_string());
+        move |source| Error::Output {
+            entry,
+            target,
+            source,
+        }
+    }
+
+    pub(crate) fn invalid(entry: &str, message: impl Into<String>) -> Error {
+        Error::Config(ConfigError::Invalid {
+            entry: entry.to_string(),
+            message: message.into(),
+        })
+    }
+}
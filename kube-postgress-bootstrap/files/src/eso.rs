@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+
+use kube::api::{Api, ApiResource, DynamicObject, GroupVersionKind, Patch, PatchParams};
+use tracing::info;
+
+use crate::{MANAGED_BY, MANAGED_BY_LABEL};
+
+// external secrets operator PushSecret propagating the generated secret to an
+// external store, from where it's synced back by the usual ExternalSecrets
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushSecretConfig {
+    secret_store: String,
+    #[serde(default = "default_secret_store_kind")]
+    secret_store_kind: String,
+    remote_key: String,
+    #[serde(default = "default_refresh_interval")]
+    refresh_interval: String,
+}
+
+fn default_secret_store_kind() -> String {
+    "SecretStore".to_string()
+}
+
+fn default_refresh_interval() -> String {
+    "1h".to_string()
+}
+
+pub async fn apply_push_secret(
+    kube_client: &kube::Client,
+    namespace: &str,
+    secret_name: &str,
+    push_secret: &PushSecretConfig,
+    data: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let gvk = GroupVersionKind::gvk("external-secrets.io", "v1alpha1", "PushSecret");
+    let push_secrets: Api<DynamicObject> = Api::namespaced_with(
+        kube_client.clone(),
+        namespace,
+        &ApiResource::from_gvk_with_plural(&gvk, "pushsecrets"),
+    );
+
+    let data: Vec<serde_json::Value> = data
+        .keys()
+        .map(|key| {
+            serde_json::json!({
+                "match": {
+                    "secretKey": key,
+                    "remoteRef": { "remoteKey": push_secret.remote_key, "property": key },
+                },
+            })
+        })
+        .collect();
+    let resource = serde_json::json!({
+        "apiVersion": "external-secrets.io/v1alpha1",
+        "kind": "PushSecret",
+        "metadata": {
+            "name": secret_name,
+            "namespace": namespace,
+            "labels": { MANAGED_BY_LABEL: MANAGED_BY },
+        },
+        "spec": {
+            "refreshInterval": push_secret.refresh_interval,
+            "secretStoreRefs": [{
+                "name": push_secret.secret_store,
+                "kind": push_secret.secret_store_kind,
+            }],
+            "selector": { "secret": { "name": secret_name } },
+            "data": data,
+        },
+    });
+
+    push_secrets
+        .patch(
+            secret_name,
+            &PatchParams::apply(MANAGED_BY),
+            &Patch::Apply(&resource),
+        )
+        .await?;
+
+    info!("successfully applied push secret: {}", secret_name);
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+use k8s_openapi::api::core::v1::ObjectReference;
+use kube::{
+    error::ErrorResponse,
+    runtime::events::{Event, EventType, Recorder, Reporter},
+};
+use tracing::warn;
+
+use crate::MANAGED_BY;
+
+static CLIENT: OnceLock<kube::Client> = OnceLock::new();
+// set when events are turned off, or once creating them is forbidden
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn configure(kube_client: &kube::Client, disabled: bool) {
+    let _ = CLIENT.set(kube_client.clone());
+    DISABLED.store(disabled, Ordering::Relaxed);
+}
+
+// records an outcome as an event about the entry's secret in the entry's
+// namespace, so it shows up in kubectl get events next to the app. the secret
+// doesn't have to exist, e.g. for failures. the reporting instance is POD_NAME.
+pub async fn publish(
+    namespace: &str,
+    secret_name: &str,
+    type_: EventType,
+    reason: &str,
+    note: String,
+) {
+    let client = match CLIENT.get() {
+        Some(client) if !DISABLED.load(Ordering::Relaxed) => client,
+        _ => return,
+    };
+    let reporter = Reporter {
+        controller: MANAGED_BY.to_string(),
+        instance: env::var("POD_NAME").ok(),
+    };
+    let reference = ObjectReference {
+        api_version: Some("v1".to_string()),
+        kind: Some("Secret".to_string()),
+        namespace: Some(namespace.to_string()),
+        name: Some(secret_name.to_string()),
+        ..Default::default()
+    };
+    let recorder = Recorder::new(client.clone(), reporter, reference);
+    // notes are limited to 1kB
+    let note: String = note.chars().take(1000).collect();
+    let event = Event {
+        type_,
+        reason: reason.to_string(),
+        note: Some(note),
+        action: reason.to_string(),
+        secondary: None,
+    };
+
+    match recorder.publish(event).await {
+        Ok(()) => {}
+        Err(kube::Error::Api(ErrorResponse { code: 403, .. })) => {
+            warn!("not allowed to create events, no more events are emitted this run");
+            DISABLED.store(true, Ordering::Relaxed);
+        }
+        Err(e) => warn!(
+            "failed to emit {} event for {}/{}: {}",
+            reason, namespace, secret_name, e
+        ),
+    }
+}
+
+pub async fn normal(namespace: &str, secret_name: &str, reason: &str, note: String) {
+    publish(namespace, secret_name, EventType::Normal, reason, note).await
+}
@@ -0,0 +1,197 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use k8s_openapi::api::core::v1::Secret;
+use kube::api::{Api, ListParams};
+use tracing::{info, warn};
+
+use crate::{
+    cli::ConfigFormat, secret_name, timing::TimedClient, DBClients, DBConnection, DatabaseConfig,
+    Error, ADMIN_SECRET_ANNOTATION, COPY_OF_ANNOTATION, DATABASES_ANNOTATION, ENGINE_ANNOTATION,
+    HOST_ANNOTATION, MANAGED_BY, MANAGED_BY_LABEL, PORT_ANNOTATION, USERNAME_ANNOTATION,
+};
+
+// an entry of the exported config, only the fields that can be read back from
+// the server and the secrets
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedEntry {
+    username: String,
+    namespace: String,
+    databases: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    extra_namespaces: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    create_secret: Option<bool>,
+    // only when the secret's name doesn't follow --secret-name-template
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret_name: Option<String>,
+}
+
+// what a secret created by the bootstrap says about its user
+struct ManagedSecret {
+    name: String,
+    namespace: String,
+    databases: Vec<String>,
+    copies: BTreeSet<String>,
+}
+
+// prints a config describing the users and databases on the default server, for
+// adopting the bootstrap on a cluster that was set up by hand. users get the
+// namespace of the secret the bootstrap created for them and the databases they
+// own or that their secret records. users without a secret are put in
+// default_namespace with createSecret: false, so bootstrapping the config keeps
+// their passwords. superusers, the admin user and pg_ roles are left out.
+pub async fn export(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    default_namespace: &str,
+    format: ConfigFormat,
+) -> Result<(), Error> {
+    let users: Vec<String> = clients
+        .admin
+        .timed_query(
+            "SELECT usename FROM pg_user WHERE NOT usesuper AND usename <> $1 AND usename NOT LIKE 'pg\\_%' ORDER BY usename",
+            &[&db_connection_details.username],
+        )
+        .await
+        .map_err(Error::db("export", "listing users"))?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+
+    let mut owned: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for row in clients
+        .admin
+        .timed_query(
+            "SELECT datname, pg_get_userbyid(datdba) FROM pg_database WHERE NOT datistemplate AND datname <> 'postgres' ORDER BY datname",
+            &[],
+        )
+        .await
+        .map_err(Error::db("export", "listing databases"))?
+        .iter()
+    {
+        owned.entry(row.get(1)).or_default().push(row.get(0));
+    }
+
+    let managed = managed_secrets(kube_client).await?;
+
+    let mut entries = Vec::new();
+    for username in users.into_iter() {
+        let secret = managed.get(&username);
+        let mut databases = owned.remove(&username).unwrap_or_default();
+        for database in secret.iter().flat_map(|secret| secret.databases.iter()) {
+            if !databases.contains(database) {
+                databases.push(database.clone());
+            }
+        }
+        if secret.is_none() {
+            warn!(
+                "user {} has no secret created by the bootstrap, exporting it with createSecret: false",
+                username
+            );
+        }
+        let namespace = secret
+            .map(|secret| secret.namespace.clone())
+            .unwrap_or_else(|| default_namespace.to_string());
+        let templated = secret_name(&DatabaseConfig {
+            username: username.clone(),
+            namespace: namespace.clone(),
+            ..Default::default()
+        });
+        entries.push(ExportedEntry {
+            secret_name: secret
+                .map(|secret| secret.name.clone())
+                .filter(|name| *name != templated),
+            namespace,
+            databases,
+            extra_namespaces: secret
+                .map(|secret| secret.copies.iter().cloned().collect())
+                .unwrap_or_default(),
+            create_secret: secret.is_none().then_some(false),
+            username,
+        });
+    }
+    info!("exported {} entries", entries.len());
+
+    let output = match format {
+        ConfigFormat::Json => {
+            serde_json::to_string_pretty(&entries).map_err(anyhow::Error::from)?
+        }
+        ConfigFormat::Yaml => serde_yaml::to_string(&entries).map_err(anyhow::Error::from)?,
+    };
+    println!("{}", output.trim_end());
+    Ok(())
+}
+
+// the secrets the bootstrap created for users on the default server, keyed by
+// username, with the namespaces their copies are in
+async fn managed_secrets(
+    kube_client: &kube::Client,
+) -> Result<BTreeMap<String, ManagedSecret>, Error> {
+    let secrets: Api<Secret> = Api::all(kube_client.clone());
+    let listed = secrets
+        .list(&ListParams::default().labels(&format!("{}={}", MANAGED_BY_LABEL, MANAGED_BY)))
+        .await
+        .map_err(Error::kube("managed secrets".to_string()))?;
+
+    let mut managed: BTreeMap<String, ManagedSecret> = BTreeMap::new();
+    let mut copies = Vec::new();
+    for secret in listed.items.into_iter() {
+        let annotations = secret.metadata.annotations.unwrap_or_default();
+        let namespace = secret.metadata.namespace.unwrap_or_default();
+        if let Some(copy_of) = annotations.get(COPY_OF_ANNOTATION) {
+            copies.push((copy_of.clone(), namespace));
+            continue;
+        }
+        // like DatabaseConfig::server, entries with their own host, port or admin
+        // secret (and other engines) are on another server
+        let other_server = [HOST_ANNOTATION, PORT_ANNOTATION, ADMIN_SECRET_ANNOTATION]
+            .iter()
+            .any(|annotation| annotations.contains_key(*annotation));
+        let engine = annotations.get(ENGINE_ANNOTATION).map(String::as_str);
+        if other_server || engine.is_some_and(|engine| engine != "postgres") {
+            continue;
+        }
+        let username = match annotations.get(USERNAME_ANNOTATION) {
+            Some(username) => username.clone(),
+            None => continue,
+        };
+        let databases = annotations
+            .get(DATABASES_ANNOTATION)
+            .iter()
+            .flat_map(|databases| databases.split(','))
+            .filter(|db| !db.is_empty())
+            .map(str::to_string)
+            .collect();
+        if let Some(previous) = managed.get(&username) {
+            warn!(
+                "user {} has secrets in namespaces {} and {}, exporting it in {}",
+                username, previous.namespace, namespace, previous.namespace
+            );
+            continue;
+        }
+        managed.insert(
+            username,
+            ManagedSecret {
+                name: secret.metadata.name.unwrap_or_default(),
+                namespace,
+                databases,
+                copies: BTreeSet::new(),
+            },
+        );
+    }
+
+    for (copy_of, namespace) in copies.into_iter() {
+        let (source_namespace, source_name) = match copy_of.split_once('/') {
+            Some(source) => source,
+            None => continue,
+        };
+        for secret in managed.values_mut() {
+            if secret.namespace == source_namespace && secret.name == source_name {
+                secret.copies.insert(namespace.clone());
+            }
+        }
+    }
+    Ok(managed)
+}
@@ -0,0 +1,108 @@
+use std::{collections::BTreeSet, sync::OnceLock};
+
+use k8s_openapi::api::core::v1::Namespace;
+use kube::api::{Api, ListParams};
+use tracing::{info, warn};
+
+use crate::{retry, DatabaseConfig, Error};
+
+struct Filters {
+    // usernames or namespace/username, from --only
+    only: Vec<String>,
+    namespace_selector: Option<String>,
+}
+
+static FILTERS: OnceLock<Filters> = OnceLock::new();
+
+// set from --only and --namespace-selector before anything runs
+pub fn configure(only: Option<String>, namespace_selector: Option<String>) {
+    let only = only
+        .iter()
+        .flat_map(|only| only.split(','))
+        .map(|entry| entry.trim().to_string())
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    let _ = FILTERS.set(Filters {
+        only,
+        namespace_selector,
+    });
+}
+
+fn filters() -> Option<&'static Filters> {
+    FILTERS
+        .get()
+        .filter(|filters| !filters.only.is_empty() || filters.namespace_selector.is_some())
+}
+
+// splits the entries into the ones to reconcile and the ones left out, with the
+// reason they were. an entry has to match both filters when both are given.
+pub async fn select(
+    kube_client: &kube::Client,
+    db_configs: Vec<DatabaseConfig>,
+) -> Result<(Vec<DatabaseConfig>, Vec<(String, String)>), Error> {
+    let filters = match filters() {
+        Some(filters) => filters,
+        None => return Ok((db_configs, Vec::new())),
+    };
+    let selected_namespaces = match &filters.namespace_selector {
+        Some(selector) => Some(matching_namespaces(kube_client, selector).await?),
+        None => None,
+    };
+
+    let mut matched_only = BTreeSet::new();
+    let mut selected = Vec::new();
+    let mut filtered = Vec::new();
+    for db_config in db_configs.into_iter() {
+        let entry = format!("{}/{}", db_config.namespace, db_config.username);
+        let only = filters
+            .only
+            .iter()
+            .find(|only| **only == db_config.username || **only == entry);
+        if let Some(only) = only {
+            matched_only.insert(only.as_str());
+        }
+        if !filters.only.is_empty() && only.is_none() {
+            filtered.push((entry, "not listed in --only".to_string()));
+        } else if selected_namespaces
+            .as_ref()
+            .is_some_and(|namespaces| !namespaces.contains(&db_config.namespace))
+        {
+            filtered.push((
+                entry,
+                "namespace not matched by --namespace-selector".to_string(),
+            ));
+        } else {
+            selected.push(db_config);
+        }
+    }
+    for only in filters.only.iter() {
+        if !matched_only.contains(only.as_str()) {
+            warn!("--only lists {}, which is not an entry of the config", only);
+        }
+    }
+    info!(
+        "reconciling {} entries, {} filtered out",
+        selected.len(),
+        filtered.len()
+    );
+    Ok((selected, filtered))
+}
+
+async fn matching_namespaces(
+    kube_client: &kube::Client,
+    selector: &str,
+) -> Result<BTreeSet<String>, Error> {
+    let namespaces: Api<Namespace> = Api::all(kube_client.clone());
+    let list_params = ListParams::default().labels(selector);
+    let resource = format!("namespaces matching {}", selector);
+    let matching = retry::retry(&resource, retry::transient_kube, || {
+        namespaces.list(&list_params)
+    })
+    .await
+    .map_err(Error::kube(resource))?;
+    Ok(matching
+        .items
+        .into_iter()
+        .filter_map(|namespace| namespace.metadata.name)
+        .collect())
+}
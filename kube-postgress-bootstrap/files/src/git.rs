@@ -0,0 +1,191 @@
+use std::{
+    env,
+    io::Write,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::Context;
+use k8s_openapi::api::core::v1::Secret;
+use tracing::{error, info};
+
+use crate::{
+    cli::{ConfigFormat, NamespaceOverrides},
+    env_number, health, heartbeat, read_config, reconcile, reload, secret_value, shutdown,
+    DBClients, DBConnection,
+};
+
+// polls a git repository and reconciles from the config file in it, for clusters
+// without flux. configured with:
+//   GIT_REPOSITORY            url to clone, ssh urls need a deploy key
+//   GIT_BRANCH                branch to track, defaults to main
+//   GIT_CONFIG_PATH           path of the config file or directory within the repository
+//   GIT_DEPLOY_KEY_SECRET     namespace/name of a secret holding the ssh private key
+//   GIT_DEPLOY_KEY_SECRET_KEY key within the secret, defaults to ssh-privatekey
+//   GIT_POLL_INTERVAL_SECONDS how often to pull, defaults to 300
+struct GitSource {
+    repository: String,
+    branch: String,
+    config_path: String,
+    checkout_dir: String,
+    ssh_command: Option<String>,
+}
+
+pub async fn run(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    format: Option<ConfigFormat>,
+    overrides: &NamespaceOverrides,
+) -> anyhow::Result<()> {
+    let git_source = GitSource {
+        repository: env::var("GIT_REPOSITORY").context("GIT_REPOSITORY must be set")?,
+        branch: env::var("GIT_BRANCH").unwrap_or_else(|_| "main".to_string()),
+        config_path: env::var("GIT_CONFIG_PATH").context("GIT_CONFIG_PATH must be set")?,
+        checkout_dir: env::var("GIT_CHECKOUT_DIR")
+            .unwrap_or_else(|_| "/tmp/kube-postgres-bootstrap-config".to_string()),
+        ssh_command: deploy_key_ssh_command(kube_client).await?,
+    };
+    let interval = Duration::from_secs(env_number("GIT_POLL_INTERVAL_SECONDS")?.unwrap_or(300));
+
+    let mut reconciled_commit: Option<String> = None;
+    loop {
+        // a failed pull keeps the last reconciled state and tries again next interval
+        match git_source.sync().await {
+            Ok(commit) if reconciled_commit.as_ref() == Some(&commit) => {
+                info!("config repository unchanged at {}", commit)
+            }
+            Ok(commit) => {
+                info!("reconciling config from commit {}", commit);
+                let config_path = Path::new(&git_source.checkout_dir).join(&git_source.config_path);
+                // a signed config needs its .sig committed alongside it. GIT_CONFIG_PATH
+                // can also be a directory of config files.
+                let result = match read_config(
+                    kube_client,
+                    &config_path.to_string_lossy(),
+                    format,
+                    overrides,
+                )
+                .await
+                {
+                    Ok(db_configs) => {
+                        reconcile(
+                            clients,
+                            kube_client,
+                            db_connection_details,
+                            "apply",
+                            db_configs,
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                };
+                // a failed reconcile is retried at the next interval, like a failed pull
+                match &result {
+                    Ok(()) => {
+                        heartbeat::success().await;
+                        reconciled_commit = Some(commit);
+                    }
+                    Err(e) => error!("failed to reconcile from commit {}: {:#}", commit, e),
+                }
+                health::cycle_finished(result.is_ok());
+            }
+            Err(e) => error!("failed to sync config repository: {:#}", e),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            // reconciles again even when the commit didn't change
+            _ = reload::wait() => reconciled_commit = None,
+            _ = shutdown::wait() => return Ok(()),
+        }
+    }
+}
+
+impl GitSource {
+    // clones or fast forwards the checkout, returning the checked out commit
+    async fn sync(&self) -> anyhow::Result<String> {
+        if Path::new(&self.checkout_dir).join(".git").exists() {
+            self.git(&["fetch", "--depth=1", "origin", &self.branch], None)
+                .await?;
+            self.git(&["reset", "--hard", "FETCH_HEAD"], None).await?;
+        } else {
+            self.git(
+                &[
+                    "clone",
+                    "--depth=1",
+                    "--branch",
+                    &self.branch,
+                    &self.repository,
+                    &self.checkout_dir,
+                ],
+                Some("/"),
+            )
+            .await?;
+        }
+
+        Ok(self
+            .git(&["rev-parse", "HEAD"], None)
+            .await?
+            .trim()
+            .to_string())
+    }
+
+    async fn git(&self, args: &[&str], dir: Option<&str>) -> anyhow::Result<String> {
+        let mut command = tokio::process::Command::new("git");
+        command
+            .args(args)
+            .current_dir(dir.unwrap_or(&self.checkout_dir));
+        if let Some(ssh_command) = &self.ssh_command {
+            command.env("GIT_SSH_COMMAND", ssh_command);
+        }
+
+        let output = command.output().await.context("failed to run git")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "git {} failed: {}",
+                args[0],
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+}
+
+// writes the deploy key out for ssh, which refuses keys readable by others
+async fn deploy_key_ssh_command(kube_client: &kube::Client) -> anyhow::Result<Option<String>> {
+    let secret_ref = match env::var("GIT_DEPLOY_KEY_SECRET") {
+        Ok(secret_ref) => secret_ref,
+        Err(_) => return Ok(None),
+    };
+    let (namespace, name) = secret_ref
+        .split_once('/')
+        .context("GIT_DEPLOY_KEY_SECRET must be formatted as namespace/name")?;
+    let key =
+        env::var("GIT_DEPLOY_KEY_SECRET_KEY").unwrap_or_else(|_| "ssh-privatekey".to_string());
+
+    let secrets: kube::api::Api<Secret> =
+        kube::api::Api::namespaced(kube_client.clone(), namespace);
+    let secret = secrets.get(name).await?;
+    let deploy_key = secret_value(&secret, &key)
+        .with_context(|| format!("deploy key secret {} has no key {}", secret_ref, key))?;
+
+    let key_path = env::temp_dir().join("kube-postgres-bootstrap-deploy-key");
+    // created 0600 so the key is never readable by others, an existing file keeps
+    // its mode and is tightened too
+    let mut key_file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&key_path)?;
+    key_file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    key_file.write_all(deploy_key.as_bytes())?;
+
+    Ok(Some(format!(
+        "ssh -i {} -o IdentitiesOnly=yes -o StrictHostKeyChecking=accept-new -o UserKnownHostsFile={}",
+        key_path.display(),
+        env::temp_dir().join("kube-postgres-bootstrap-known-hosts").display()
+    )))
+}
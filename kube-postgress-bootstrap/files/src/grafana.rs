@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::Secret;
+use kube::api::{Api, Patch, PatchParams};
+use tracing::info;
+
+use crate::{DBConnection, MANAGED_BY, MANAGED_BY_LABEL};
+
+// grafana datasource provisioning file for the entry's user, picked up by the
+// grafana helm chart's sidecar through the grafana_datasource label
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrafanaDatasourceConfig {
+    // namespace grafana runs in, defaults to the entry's namespace
+    namespace: Option<String>,
+    // datasource name shown in grafana, defaults to the username
+    name: Option<String>,
+    // defaults to the entry's first database
+    database: Option<String>,
+    #[serde(default = "default_sidecar_label")]
+    label: String,
+}
+
+impl GrafanaDatasourceConfig {
+    // namespace and name of the datasource secret, kept when pruning
+    pub fn secret_ref(&self, entry_namespace: &str, username: &str) -> (String, String) {
+        (
+            self.namespace
+                .clone()
+                .unwrap_or_else(|| entry_namespace.to_string()),
+            format!("{}-grafana-datasource", username),
+        )
+    }
+}
+
+fn default_sidecar_label() -> String {
+    "grafana_datasource".to_string()
+}
+
+pub async fn apply_datasource(
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    datasource: &GrafanaDatasourceConfig,
+    entry_namespace: &str,
+    username: &str,
+    password: &str,
+    databases: &[String],
+) -> anyhow::Result<()> {
+    let (namespace, secret_name) = datasource.secret_ref(entry_namespace, username);
+    let name = datasource.name.as_deref().unwrap_or(username);
+    let database = datasource
+        .database
+        .as_deref()
+        .or(databases.first().map(String::as_str))
+        .unwrap_or_default();
+
+    // json strings double as quoted yaml scalars, so values don't need escaping
+    let quote = |value: &str| serde_json::Value::from(value).to_string();
+    let provisioning = format!(
+        "apiVersion: 1
+datasources:
+  - name: {}
+    type: postgres
+    url: {}
+    user: {}
+    jsonData:
+      database: {}
+      sslmode: disable
+    secureJsonData:
+      password: {}
+",
+        quote(name),
+        quote(&format!(
+            "{}:{}",
+            db_connection_details.host, db_connection_details.port
+        )),
+        quote(username),
+        quote(database),
+        quote(password),
+    );
+
+    let secret = Secret {
+        metadata: k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta {
+            name: Some(secret_name.clone()),
+            namespace: Some(namespace.clone()),
+            labels: Some(BTreeMap::from([
+                (MANAGED_BY_LABEL.to_string(), MANAGED_BY.to_string()),
+                (datasource.label.clone(), "1".to_string()),
+            ])),
+            ..Default::default()
+        },
+        string_data: Some(BTreeMap::from([(
+            format!("{}-datasource.yaml", username),
+            provisioning,
+        )])),
+        ..Default::default()
+    };
+
+    let secrets: Api<Secret> = Api::namespaced(kube_client.clone(), &namespace);
+    secrets
+        .patch(
+            &secret_name,
+            &PatchParams::apply(MANAGED_BY),
+            &Patch::Apply(&secret),
+        )
+        .await?;
+
+    info!(
+        "successfully applied grafana datasource secret: {}/{}",
+        namespace, secret_name
+    );
+    Ok(())
+}
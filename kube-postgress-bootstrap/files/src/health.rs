@@ -0,0 +1,53 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+
+use crate::{try_connect, DBConnection};
+
+// how long a probe waits on postgres or the api server before calling it wedged
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+static TARGETS: OnceLock<(String, kube::Client)> = OnceLock::new();
+// set by the long running modes once a reconcile cycle finished, cleared again
+// when one fails
+static READY: AtomicBool = AtomicBool::new(false);
+
+pub fn configure(db_connection_details: &DBConnection, kube_client: &kube::Client) {
+    let _ = TARGETS.set((
+        db_connection_details.connection_string(),
+        kube_client.clone(),
+    ));
+}
+
+pub fn cycle_finished(success: bool) {
+    READY.store(success, Ordering::Relaxed);
+}
+
+pub fn ready() -> bool {
+    READY.load(Ordering::Relaxed)
+}
+
+// for /healthz: the admin credentials still get a SELECT 1 through and the api
+// server answers a version request, each within CHECK_TIMEOUT. the queries
+// aren't timed so probes don't show up in the operation metrics.
+pub async fn live() -> anyhow::Result<()> {
+    let (connection_string, kube_client) = TARGETS.get().context("not configured")?;
+    tokio::time::timeout(CHECK_TIMEOUT, async {
+        let client = try_connect(connection_string).await?;
+        client.execute("SELECT 1", &[]).await
+    })
+    .await
+    .context("postgres did not answer in time")?
+    .context("postgres check failed")?;
+    tokio::time::timeout(CHECK_TIMEOUT, kube_client.apiserver_version())
+        .await
+        .context("the kubernetes api did not answer in time")?
+        .context("kubernetes api check failed")?;
+    Ok(())
+}
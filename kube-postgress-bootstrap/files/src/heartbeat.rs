@@ -0,0 +1,31 @@
+use std::env;
+
+use tracing::warn;
+
+// pings HEARTBEAT_URL after a successful run and HEARTBEAT_FAILURE_URL (e.g. the
+// healthchecks.io /fail endpoint) with the error when a run fails, so missed or
+// failing runs get noticed
+pub async fn success() {
+    if let Ok(url) = env::var("HEARTBEAT_URL") {
+        ping(&url, String::new()).await;
+    }
+}
+
+pub async fn failure(error: &str) {
+    if let Ok(url) = env::var("HEARTBEAT_FAILURE_URL") {
+        ping(&url, error.to_string()).await;
+    }
+}
+
+// a failed ping shouldn't fail the run, a missing ping alerts on its own
+async fn ping(url: &str, body: String) {
+    let result = reqwest::Client::new()
+        .post(url)
+        .body(body)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    if let Err(e) = result {
+        warn!("failed to ping heartbeat url: {}", e);
+    }
+}
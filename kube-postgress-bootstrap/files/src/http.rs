@@ -0,0 +1,64 @@
+use anyhow::Context;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{info, warn};
+
+use crate::{health, metrics};
+
+// serves /metrics, /healthz and /readyz on addr (e.g. 0.0.0.0:9090) for the long
+// running modes. binding happens before returning so a taken port fails the
+// start, requests are answered in the background.
+pub async fn serve(addr: &str) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to listen on {}", addr))?;
+    info!("serving /metrics, /healthz and /readyz on {}", addr);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(respond(stream));
+                }
+                Err(e) => warn!("failed to accept http connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn respond(mut stream: TcpStream) {
+    // only the request line matters, scrapes and probes have no body
+    let mut buffer = [0; 1024];
+    let read = stream.read(&mut buffer).await.unwrap_or(0);
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let path = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", path] => path.split('?').next().unwrap_or_default(),
+        _ => "",
+    };
+    let (status, body) = match path {
+        "/metrics" => ("200 OK", metrics::render()),
+        "/healthz" => match health::live().await {
+            Ok(()) => ("200 OK", "ok\n".to_string()),
+            Err(e) => {
+                warn!("health check failed: {:#}", e);
+                ("503 Service Unavailable", format!("{:#}\n", e))
+            }
+        },
+        "/readyz" if health::ready() => ("200 OK", "ok\n".to_string()),
+        "/readyz" => (
+            "503 Service Unavailable",
+            "the last reconcile cycle did not complete\n".to_string(),
+        ),
+        _ => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
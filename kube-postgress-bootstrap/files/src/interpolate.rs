@@ -0,0 +1,133 @@
+use std::env;
+
+use crate::ConfigError;
+
+// expands ${VAR} and ${VAR:-default} in every string value of the config (not in
+// keys), e.g. "namespace": "app-${ENVIRONMENT}" so staging and production share a
+// file. $${ is a literal ${. a variable that isn't set and has no default fails
+// loading the config instead of provisioning e.g. the namespace "app-".
+pub fn interpolate(value: &mut serde_json::Value) -> Result<(), ConfigError> {
+    match value {
+        serde_json::Value::String(string) if string.contains("${") => {
+            *string = expand(string)?;
+        }
+        serde_json::Value::Array(values) => {
+            for value in values.iter_mut() {
+                interpolate(value)?;
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for value in fields.values_mut() {
+                interpolate(value)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn expand(string: &str) -> Result<String, ConfigError> {
+    let mut expanded = String::with_capacity(string.len());
+    let mut rest = string;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| invalid(string, "has an unclosed ${"))?;
+        let reference = &rest[start + 2..start + end];
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(invalid(
+                string,
+                &format!("references ${{{}}}, which isn't a variable name", reference),
+            ));
+        }
+        match (env::var(name), default) {
+            (Ok(value), _) => expanded.push_str(&value),
+            (Err(_), Some(default)) => expanded.push_str(default),
+            (Err(_), None) => {
+                return Err(invalid(
+                    string,
+                    &format!("references ${{{}}}, which is not set", name),
+                ))
+            }
+        }
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn invalid(string: &str, message: &str) -> ConfigError {
+    ConfigError::Invalid {
+        entry: "config".to_string(),
+        message: format!("{:?} {}", string, message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_variables() {
+        env::set_var("INTERPOLATE_TEST_ENVIRONMENT", "staging");
+        assert_eq!(
+            expand("app-${INTERPOLATE_TEST_ENVIRONMENT}").unwrap(),
+            "app-staging"
+        );
+        assert_eq!(
+            expand("${INTERPOLATE_TEST_ENVIRONMENT}-${INTERPOLATE_TEST_ENVIRONMENT}").unwrap(),
+            "staging-staging"
+        );
+    }
+
+    #[test]
+    fn defaults_apply_to_unset_variables() {
+        assert_eq!(
+            expand("app-${INTERPOLATE_TEST_UNSET:-dev}").unwrap(),
+            "app-dev"
+        );
+        assert_eq!(expand("app${INTERPOLATE_TEST_UNSET:-}").unwrap(), "app");
+        env::set_var("INTERPOLATE_TEST_SET", "prod");
+        assert_eq!(expand("${INTERPOLATE_TEST_SET:-dev}").unwrap(), "prod");
+    }
+
+    #[test]
+    fn double_dollar_is_a_literal() {
+        assert_eq!(expand("$${HOME}").unwrap(), "${HOME}");
+        assert_eq!(
+            expand("a $${literal} and ${INTERPOLATE_TEST_UNSET:-b}").unwrap(),
+            "a ${literal} and b"
+        );
+    }
+
+    #[test]
+    fn rejects_unusable_references() {
+        assert!(expand("app-${INTERPOLATE_TEST_UNSET}").is_err());
+        assert!(expand("app-${INTERPOLATE_TEST_UNSET").is_err());
+        assert!(expand("app-${}").is_err());
+        assert!(expand("app-${not-a-name}").is_err());
+    }
+
+    #[test]
+    fn interpolates_values_but_not_keys() {
+        let mut value = serde_json::json!({
+            "${INTERPOLATE_TEST_UNSET:-key}": ["${INTERPOLATE_TEST_UNSET:-value}", 1],
+        });
+        interpolate(&mut value).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({ "${INTERPOLATE_TEST_UNSET:-key}": ["value", 1] })
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, OnceLock},
+};
+
+use k8s_openapi::{
+    api::core::v1::ConfigMap,
+    chrono::{SecondsFormat, Utc},
+};
+use kube::{
+    api::{Api, Patch, PatchParams, PostParams},
+    error::ErrorResponse,
+};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::{
+    retry, secret_name, ConfigError, DBConnection, DatabaseConfig, Error, MANAGED_BY,
+    MANAGED_BY_LABEL,
+};
+
+// namespace and name of the inventory configmap, when --inventory is set
+static LOCATION: OnceLock<Option<(String, String)>> = OnceLock::new();
+// entries reconciled since the last flush
+static PENDING: Mutex<BTreeMap<String, Record>> = Mutex::new(BTreeMap::new());
+
+pub fn configure(location: Option<String>) -> Result<(), ConfigError> {
+    let location = match location {
+        Some(location) => {
+            let (namespace, name) = location
+                .split_once('/')
+                .ok_or_else(|| ConfigError::setting("inventory", "must be namespace/name"))?;
+            Some((namespace.to_string(), name.to_string()))
+        }
+        None => None,
+    };
+    let _ = LOCATION.set(location);
+    Ok(())
+}
+
+fn location() -> Option<&'static (String, String)> {
+    LOCATION.get().and_then(Option::as_ref)
+}
+
+// what the bootstrap created for an entry, stored as json under
+// <namespace>.<username> in the inventory configmap. created_at is kept from the
+// first reconcile, config_hash changes whenever the entry in the config does.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Record {
+    pub namespace: String,
+    pub username: String,
+    pub engine: String,
+    pub host: String,
+    pub databases: Vec<String>,
+    pub secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub copies: Vec<String>,
+    pub config_hash: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl Record {
+    // taken before the entry is reconciled, it's only recorded once that worked
+    pub fn new(db_config: &DatabaseConfig, db_connection_details: &DBConnection) -> Record {
+        let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        let secret = secret_name(db_config);
+        Record {
+            namespace: db_config.namespace.clone(),
+            username: db_config.username.clone(),
+            engine: db_config.engine.as_str().to_string(),
+            host: db_config
+                .host
+                .clone()
+                .unwrap_or_else(|| db_connection_details.host.clone()),
+            databases: if db_config.provisions_databases() {
+                db_config.databases.clone()
+            } else {
+                Vec::new()
+            },
+            secret: db_config.create_secret().then(|| secret.clone()),
+            copies: db_config
+                .extra_namespaces
+                .iter()
+                .map(|namespace| format!("{}/{}", namespace, secret))
+                .collect(),
+            // the debug output covers every field of the entry
+            config_hash: format!("{:x}", Sha256::digest(format!("{:?}", db_config)))[..16]
+                .to_string(),
+            created_at: now.clone(),
+            updated_at: now,
+        }
+    }
+}
+
+fn key(namespace: &str, username: &str) -> String {
+    format!("{}.{}", namespace, username)
+}
+
+pub fn record(record: Record) {
+    if location().is_none() {
+        return;
+    }
+    PENDING
+        .lock()
+        .unwrap()
+        .insert(key(&record.namespace, &record.username), record);
+}
+
+// writes the entries recorded since the last flush to the configmap, with a merge
+// patch so entries written by other runs are kept
+pub async fn flush(kube_client: &kube::Client) -> Result<(), Error> {
+    let (namespace, name) = match location() {
+        Some(location) => location,
+        None => return Ok(()),
+    };
+    let mut records = std::mem::take(&mut *PENDING.lock().unwrap());
+    if records.is_empty() {
+        return Ok(());
+    }
+    let config_maps: Api<ConfigMap> = Api::namespaced(kube_client.clone(), namespace);
+    let resource = format!("configmap {}/{}", namespace, name);
+    let existing = retry::retry(&resource, retry::transient_kube, || {
+        config_maps.get_opt(name)
+    })
+    .await
+    .map_err(Error::kube(resource.clone()))?;
+
+    let previous = existing
+        .as_ref()
+        .and_then(|config_map| config_map.data.clone())
+        .unwrap_or_default();
+    for (key, record) in records.iter_mut() {
+        if let Some(created_at) = previous
+            .get(key)
+            .and_then(|value| serde_json::from_str::<Record>(value).ok())
+            .map(|previous| previous.created_at)
+        {
+            record.created_at = created_at;
+        }
+    }
+    let data: BTreeMap<String, String> = records
+        .iter()
+        .map(|(key, record)| (key.clone(), serde_json::to_string(record).unwrap()))
+        .collect();
+
+    match existing {
+        None => {
+            let post_params = PostParams::default();
+            let config_map = ConfigMap {
+                metadata: k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta {
+                    name: Some(name.clone()),
+                    namespace: Some(namespace.clone()),
+                    labels: Some(BTreeMap::from([(
+                        MANAGED_BY_LABEL.to_string(),
+                        MANAGED_BY.to_string(),
+                    )])),
+                    ..Default::default()
+                },
+                data: Some(data),
+                ..Default::default()
+            };
+            retry::retry(&resource, retry::transient_kube, || {
+                config_maps.create(&post_params, &config_map)
+            })
+            .await
+            .map_err(Error::kube(resource))?;
+        }
+        Some(_) => {
+            let patch_params = PatchParams::default();
+            let patch = Patch::Merge(serde_json::json!({ "data": data }));
+            retry::retry(&resource, retry::transient_kube, || {
+                config_maps.patch(name, &patch_params, &patch)
+            })
+            .await
+            .map_err(Error::kube(resource))?;
+        }
+    }
+    info!(
+        "recorded {} entries in inventory {}/{}",
+        records.len(),
+        namespace,
+        name
+    );
+    Ok(())
+}
+
+// drops a deleted or pruned entry from the configmap
+pub async fn remove(
+    kube_client: &kube::Client,
+    namespace: &str,
+    username: &str,
+) -> Result<(), Error> {
+    let (inventory_namespace, name) = match location() {
+        Some(location) => location,
+        None => return Ok(()),
+    };
+    PENDING.lock().unwrap().remove(&key(namespace, username));
+    let config_maps: Api<ConfigMap> = Api::namespaced(kube_client.clone(), inventory_namespace);
+    let resource = format!("configmap {}/{}", inventory_namespace, name);
+    let patch_params = PatchParams::default();
+    let patch = Patch::Merge(serde_json::json!({
+        "data": { key(namespace, username): null },
+    }));
+    match retry::retry(&resource, retry::transient_kube, || {
+        config_maps.patch(name, &patch_params, &patch)
+    })
+    .await
+    {
+        Ok(_) | Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => Ok(()),
+        Err(e) => Err(Error::kube(resource)(e)),
+    }
+}
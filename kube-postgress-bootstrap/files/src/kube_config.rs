@@ -0,0 +1,44 @@
+use std::sync::OnceLock;
+
+use kube::config::{KubeConfigOptions, Kubeconfig};
+
+use crate::Error;
+
+static OPTIONS: OnceLock<(Option<String>, Option<String>)> = OnceLock::new();
+
+pub fn configure(kubeconfig: Option<String>, context: Option<String>) {
+    let _ = OPTIONS.set((kubeconfig, context));
+}
+
+// the config of --kubeconfig and --context, for runs from outside the cluster.
+// without either it's inferred: the in cluster config, $KUBECONFIG or
+// ~/.kube/config with its current context.
+pub async fn load() -> Result<kube::Config, Error> {
+    let (kubeconfig, context) = match OPTIONS.get() {
+        Some((None, None)) | None => {
+            return kube::Config::infer()
+                .await
+                .map_err(|e| Error::kube("client config".to_string())(kube::Error::InferConfig(e)))
+        }
+        Some(options) => options,
+    };
+    let options = KubeConfigOptions {
+        context: context.clone(),
+        ..Default::default()
+    };
+    let config = match kubeconfig {
+        Some(path) => {
+            let kubeconfig = Kubeconfig::read_from(path)
+                .map_err(|e| anyhow::anyhow!("failed to read kubeconfig {}: {}", path, e))?;
+            kube::Config::from_custom_kubeconfig(kubeconfig, &options).await
+        }
+        None => kube::Config::from_kubeconfig(&options).await,
+    };
+    config.map_err(|e| {
+        Error::Other(anyhow::anyhow!(
+            "failed to load context {}: {}",
+            context.as_deref().unwrap_or("(current)"),
+            e
+        ))
+    })
+}
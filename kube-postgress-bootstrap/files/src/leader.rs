@@ -0,0 +1,144 @@
+use std::{
+    env,
+    future::Future,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use k8s_openapi::{
+    api::coordination::v1::{Lease, LeaseSpec},
+    apimachinery::pkg::apis::meta::v1::{MicroTime, ObjectMeta},
+    chrono::{self, Utc},
+};
+use kube::{
+    api::{Api, PostParams},
+    error::ErrorResponse,
+};
+use tracing::{info, warn};
+
+use crate::{shutdown, MANAGED_BY, MANAGED_BY_LABEL};
+
+// a standby takes over once the leader hasn't renewed for LEASE_DURATION
+const LEASE_DURATION: Duration = Duration::from_secs(15);
+const RENEW_INTERVAL: Duration = Duration::from_secs(5);
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+// the lease name when leader election is on
+static LEASE: OnceLock<Option<String>> = OnceLock::new();
+
+pub fn configure(lease: Option<String>) {
+    let _ = LEASE.set(lease);
+}
+
+// runs the long running mode only while holding the coordination.k8s.io lease,
+// so several replicas don't race on creating the same users and secrets. the
+// lease lives in POD_NAMESPACE (or the client's default namespace) and is held
+// by POD_NAME or HOSTNAME. losing it stops the mode like a shutdown, the entries
+// in flight are finished and it returns an error, the pod is restarted and waits
+// as a standby.
+pub async fn while_leading(
+    kube_client: &kube::Client,
+    run: impl Future<Output = anyhow::Result<()>>,
+) -> anyhow::Result<()> {
+    let name = match LEASE.get() {
+        Some(Some(name)) => name,
+        _ => return run.await,
+    };
+    let identity = env::var("POD_NAME")
+        .or_else(|_| env::var("HOSTNAME"))
+        .unwrap_or_else(|_| format!("pid-{}", std::process::id()));
+    let leases: Api<Lease> = match env::var("POD_NAMESPACE") {
+        Ok(namespace) => Api::namespaced(kube_client.clone(), &namespace),
+        Err(_) => Api::default_namespaced(kube_client.clone()),
+    };
+    let resource = format!("lease {}", name);
+
+    info!("waiting to acquire {} as {}", resource, identity);
+    loop {
+        match try_acquire(&leases, name, &identity).await {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(e) => warn!("failed to acquire {}: {}", resource, e),
+        }
+        tokio::time::sleep(RETRY_INTERVAL).await;
+    }
+    info!("acquired {}, reconciling as the leader", resource);
+
+    let renew = async {
+        let mut renewed = Instant::now();
+        loop {
+            tokio::time::sleep(RENEW_INTERVAL).await;
+            match try_acquire(&leases, name, &identity).await {
+                Ok(true) => renewed = Instant::now(),
+                Ok(false) => anyhow::bail!("lost {} to another instance", resource),
+                // errors are retried until a standby could have taken over
+                Err(e) if renewed.elapsed() < LEASE_DURATION => {
+                    warn!("failed to renew {}: {}", resource, e)
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to renew {} in time", resource))
+                }
+            }
+        }
+    };
+    tokio::pin!(run);
+    let lost = tokio::select! {
+        result = &mut run => return result,
+        result = renew => result,
+    };
+    shutdown::request();
+    if let Err(e) = run.await {
+        warn!("stopping after losing {}: {:#}", resource, e);
+    }
+    lost
+}
+
+// takes or renews the lease, false while another instance holds it. a conflict
+// means another instance updated it first.
+async fn try_acquire(leases: &Api<Lease>, name: &str, identity: &str) -> Result<bool, kube::Error> {
+    let now = Utc::now();
+    let existing = leases.get_opt(name).await?;
+    let mut lease = existing.clone().unwrap_or_else(|| Lease {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            labels: Some([(MANAGED_BY_LABEL.to_string(), MANAGED_BY.to_string())].into()),
+            ..Default::default()
+        },
+        spec: None,
+    });
+    let mut spec = lease.spec.take().unwrap_or_default();
+    let held = spec.holder_identity.as_deref() == Some(identity);
+    let expired = match (&spec.renew_time, spec.lease_duration_seconds) {
+        (Some(MicroTime(renewed)), Some(seconds)) => {
+            *renewed + chrono::Duration::seconds(seconds.into()) < now
+        }
+        _ => true,
+    };
+    if !held && !expired {
+        return Ok(false);
+    }
+
+    if !held {
+        spec = LeaseSpec {
+            holder_identity: Some(identity.to_string()),
+            acquire_time: Some(MicroTime(now)),
+            lease_transitions: Some(spec.lease_transitions.unwrap_or_default() + 1),
+            ..spec
+        };
+    }
+    spec.renew_time = Some(MicroTime(now));
+    spec.lease_duration_seconds = Some(LEASE_DURATION.as_secs() as i32);
+    lease.spec = Some(spec);
+
+    let result = match existing {
+        None => leases.create(&PostParams::default(), &lease).await,
+        // the resource version makes this fail when the lease changed since the get
+        Some(_) => leases.replace(name, &PostParams::default(), &lease).await,
+    };
+    match result {
+        Ok(_) => Ok(true),
+        Err(kube::Error::Api(ErrorResponse { code: 409, .. })) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
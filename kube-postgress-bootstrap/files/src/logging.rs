@@ -0,0 +1,26 @@
+use tracing::level_filters::LevelFilter;
+
+use crate::ConfigError;
+
+// human readable lines by default, --log-format=json writes one object per line
+// with the fields of the current span (username, namespace, database) under
+// "span" so runs can be filtered in loki or elasticsearch
+pub fn init(level: &str, format: &str) -> Result<(), ConfigError> {
+    let level: LevelFilter = level.parse().map_err(|_| {
+        ConfigError::setting("log-level", format!("{:?} is not a log level", level))
+    })?;
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_target(false);
+    // only fails when a subscriber is already installed, e.g. run twice in process
+    let _ = match format {
+        "json" => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .try_init(),
+        _ => builder.try_init(),
+    };
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::OnceLock,
+};
+
+use anyhow::Context;
+use k8s_openapi::api::core::v1::Secret;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use crate::{cli::ManifestFormat, USERNAME_ANNOTATION};
+
+struct ManifestOutput {
+    dir: PathBuf,
+    format: ManifestFormat,
+    secret_store: Option<String>,
+}
+
+static OUTPUT: OnceLock<Option<ManifestOutput>> = OnceLock::new();
+
+pub fn configure(dir: Option<String>, format: ManifestFormat, secret_store: Option<String>) {
+    let _ = OUTPUT.set(dir.map(|dir| ManifestOutput {
+        dir: PathBuf::from(dir),
+        format,
+        secret_store,
+    }));
+}
+
+// secrets are written to disk instead of being created through the api, the
+// api is still read for the secrets that already exist
+pub fn enabled() -> bool {
+    matches!(OUTPUT.get(), Some(Some(_)))
+}
+
+// writes the secret to <dir>/<namespace>/<name>.yaml in the configured format
+pub async fn write(secret: &Secret) -> anyhow::Result<()> {
+    let output = match OUTPUT.get() {
+        Some(Some(output)) => output,
+        _ => return Ok(()),
+    };
+    let namespace = secret.metadata.namespace.as_deref().unwrap_or_default();
+    let name = secret.metadata.name.as_deref().unwrap_or_default();
+    let manifest = match output.format {
+        ManifestFormat::Secret => serde_yaml::to_string(secret)?,
+        ManifestFormat::SealedSecret => seal(secret).await?,
+        ManifestFormat::ExternalSecret => {
+            let secret_store = output
+                .secret_store
+                .as_ref()
+                .context("--manifests-secret-store is required for external-secret manifests")?;
+            serde_yaml::to_string(&external_secret(secret, secret_store))?
+        }
+    };
+
+    let path = Path::new(&output.dir)
+        .join(namespace)
+        .join(format!("{}.yaml", name));
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    std::fs::write(&path, manifest)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    info!(
+        "wrote manifest of secret {}/{} to {}",
+        namespace,
+        name,
+        path.display()
+    );
+    Ok(())
+}
+
+async fn seal(secret: &Secret) -> anyhow::Result<String> {
+    let mut command = tokio::process::Command::new("kubeseal");
+    command.args(["--format", "yaml"]);
+    if let Ok(cert) = std::env::var("SEALED_SECRETS_CERT") {
+        command.args(["--cert", &cert]);
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("failed to run kubeseal")?;
+    let mut stdin = child.stdin.take().unwrap();
+    stdin
+        .write_all(serde_json::to_string(secret)?.as_bytes())
+        .await?;
+    drop(stdin);
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        anyhow::bail!("kubeseal failed with {}", output.status);
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+// the remote key is <namespace>/<username>, the default path of the vault and
+// aws secret stores
+fn external_secret(secret: &Secret, secret_store: &str) -> serde_json::Value {
+    let annotations = secret.metadata.annotations.clone().unwrap_or_default();
+    let username = annotations
+        .get(USERNAME_ANNOTATION)
+        .cloned()
+        .unwrap_or_default();
+    let mut template = serde_json::json!({
+        "metadata": {
+            "labels": secret.metadata.labels,
+            "annotations": annotations,
+        },
+    });
+    if let Some(secret_type) = &secret.type_ {
+        template["type"] = serde_json::Value::String(secret_type.clone());
+    }
+    serde_json::json!({
+        "apiVersion": "external-secrets.io/v1beta1",
+        "kind": "ExternalSecret",
+        "metadata": {
+            "name": secret.metadata.name,
+            "namespace": secret.metadata.namespace,
+            "labels": secret.metadata.labels,
+        },
+        "spec": {
+            "refreshInterval": "1h",
+            "secretStoreRef": { "name": secret_store, "kind": "ClusterSecretStore" },
+            "target": {
+                "name": secret.metadata.name,
+                "template": template,
+            },
+            "dataFrom": [{
+                "extract": {
+                    "key": format!(
+                        "{}/{}",
+                        secret.metadata.namespace.as_deref().unwrap_or_default(),
+                        username
+                    ),
+                },
+            }],
+        },
+    })
+}
@@ -0,0 +1,208 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{timing, Error};
+
+const PREFIX: &str = "kube_postgres_bootstrap";
+// upper bounds in seconds of the per-entry duration buckets
+const DURATION_BUCKETS: [f64; 10] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+static ENTRIES_SUCCEEDED: AtomicU64 = AtomicU64::new(0);
+static ENTRIES_FAILED: AtomicU64 = AtomicU64::new(0);
+static USERS_CREATED: AtomicU64 = AtomicU64::new(0);
+static SECRETS_CREATED: AtomicU64 = AtomicU64::new(0);
+static SECRETS_UPDATED: AtomicU64 = AtomicU64::new(0);
+// failed entries by the kind of error, see failure_type
+static FAILURES: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+static DURATIONS: Mutex<Histogram> = Mutex::new(Histogram {
+    buckets: [0; DURATION_BUCKETS.len()],
+    count: 0,
+    sum: 0.0,
+});
+
+struct Histogram {
+    buckets: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+pub fn user_created() {
+    USERS_CREATED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn secret_created() {
+    SECRETS_CREATED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn secret_updated() {
+    SECRETS_UPDATED.fetch_add(1, Ordering::Relaxed);
+}
+
+// counts a processed entry, failure is the failure_type of a failed one
+pub fn entry_processed(failure: Option<&'static str>, elapsed: Duration) {
+    match failure {
+        None => ENTRIES_SUCCEEDED.fetch_add(1, Ordering::Relaxed),
+        Some(failure) => {
+            *FAILURES.lock().unwrap().entry(failure).or_default() += 1;
+            ENTRIES_FAILED.fetch_add(1, Ordering::Relaxed)
+        }
+    };
+
+    let seconds = elapsed.as_secs_f64();
+    let mut durations = DURATIONS.lock().unwrap();
+    for (bucket, bound) in durations.buckets.iter_mut().zip(DURATION_BUCKETS) {
+        if seconds <= bound {
+            *bucket += 1;
+        }
+    }
+    durations.count += 1;
+    durations.sum += seconds;
+}
+
+pub fn failure_type(error: &Error) -> &'static str {
+    match error {
+        Error::Config(_) => "config",
+        Error::Db(_) => "database",
+        Error::MySql(_) => "mysql",
+        Error::Kube(_) => "kubernetes",
+        Error::Timeout { .. } | Error::OperationTimeout { .. } => "timeout",
+        Error::Failed { .. } => "failed",
+        Error::Standby { .. } => "standby",
+        Error::Output { .. } => "output",
+        Error::Other(_) => "other",
+    }
+}
+
+// the prometheus text format of every metric, including the sql and kube
+// operation timings
+pub fn render() -> String {
+    let mut out = String::new();
+    let counter = |out: &mut String, name: &str, help: &str| {
+        writeln!(out, "# HELP {}_{} {}", PREFIX, name, help).unwrap();
+        writeln!(out, "# TYPE {}_{} counter", PREFIX, name).unwrap();
+    };
+
+    counter(&mut out, "entries_processed_total", "entries reconciled");
+    for (result, count) in [
+        ("success", &ENTRIES_SUCCEEDED),
+        ("failure", &ENTRIES_FAILED),
+    ] {
+        writeln!(
+            out,
+            "{}_entries_processed_total{{result=\"{}\"}} {}",
+            PREFIX,
+            result,
+            count.load(Ordering::Relaxed)
+        )
+        .unwrap();
+    }
+    for (name, help, count) in [
+        (
+            "users_created_total",
+            "database users created",
+            &USERS_CREATED,
+        ),
+        ("secrets_created_total", "secrets created", &SECRETS_CREATED),
+        (
+            "secrets_updated_total",
+            "secrets updated or rotated",
+            &SECRETS_UPDATED,
+        ),
+    ] {
+        counter(&mut out, name, help);
+        writeln!(out, "{}_{} {}", PREFIX, name, count.load(Ordering::Relaxed)).unwrap();
+    }
+
+    counter(&mut out, "failures_total", "failed entries by error type");
+    for (failure, count) in FAILURES.lock().unwrap().iter() {
+        writeln!(
+            out,
+            "{}_failures_total{{type=\"{}\"}} {}",
+            PREFIX, failure, count
+        )
+        .unwrap();
+    }
+
+    let durations = DURATIONS.lock().unwrap();
+    writeln!(
+        out,
+        "# HELP {}_entry_duration_seconds time to reconcile an entry",
+        PREFIX
+    )
+    .unwrap();
+    writeln!(out, "# TYPE {}_entry_duration_seconds histogram", PREFIX).unwrap();
+    for (count, bound) in durations.buckets.iter().zip(DURATION_BUCKETS) {
+        writeln!(
+            out,
+            "{}_entry_duration_seconds_bucket{{le=\"{}\"}} {}",
+            PREFIX, bound, count
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "{}_entry_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+        PREFIX, durations.count
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "{}_entry_duration_seconds_sum {}",
+        PREFIX, durations.sum
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "{}_entry_duration_seconds_count {}",
+        PREFIX, durations.count
+    )
+    .unwrap();
+    drop(durations);
+
+    let timings = timing::snapshot();
+    counter(
+        &mut out,
+        "operations_total",
+        "sql statements and kube api calls",
+    );
+    for (operation, timing) in timings.iter() {
+        writeln!(
+            out,
+            "{}_operations_total{{operation=\"{}\"}} {}",
+            PREFIX,
+            escape_label(operation),
+            timing.count
+        )
+        .unwrap();
+    }
+    counter(
+        &mut out,
+        "operation_seconds_total",
+        "time spent in sql statements and kube api calls",
+    );
+    for (operation, timing) in timings.iter() {
+        writeln!(
+            out,
+            "{}_operation_seconds_total{{operation=\"{}\"}} {}",
+            PREFIX,
+            escape_label(operation),
+            timing.total.as_secs_f64()
+        )
+        .unwrap();
+    }
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
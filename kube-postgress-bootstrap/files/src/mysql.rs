@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+
+use mysql_async::{prelude::Queryable, Conn, OptsBuilder, SslOpts};
+
+use crate::{
+    percent_encode,
+    provisioner::{self, Provisioner},
+    timeouts, timing, tls, uri_host, DBConnection, DatabaseConfig, Error,
+};
+
+const DEFAULT_PORT: u16 = 3306;
+
+// a mysql or mariadb server, for entries with engine: mysql. the entry's host is
+// required, the admin credentials come from its adminSecret or DB_USERNAME and
+// DB_PASSWORD. users are created for any host ('%').
+pub(crate) struct MySql {
+    conn: Conn,
+    host: String,
+    port: u16,
+    ssl_mode: String,
+    entry: String,
+}
+
+impl MySql {
+    pub async fn connect(
+        kube_client: &kube::Client,
+        default: &DBConnection,
+        db_config: &DatabaseConfig,
+    ) -> Result<MySql, Error> {
+        let host = db_config.host.clone().ok_or_else(|| {
+            Error::invalid(&db_config.username, "engine mysql needs the entry's host")
+        })?;
+        let port = db_config.port.unwrap_or(DEFAULT_PORT);
+        let (username, password) =
+            provisioner::admin_credentials(kube_client, default, db_config).await?;
+        let ssl_mode = db_config.ssl_mode.clone().unwrap_or_else(tls::ssl_mode);
+        // the same meaning as for postgres: require only encrypts, verify-ca also
+        // checks the certificate and verify-full the host name too
+        let ssl_opts = match ssl_mode.as_str() {
+            "require" => Some(SslOpts::default().with_danger_accept_invalid_certs(true)),
+            "verify-ca" => Some(SslOpts::default().with_danger_skip_domain_validation(true)),
+            "verify-full" => Some(SslOpts::default()),
+            _ => None,
+        };
+        let opts = OptsBuilder::default()
+            .ip_or_hostname(host.clone())
+            .tcp_port(port)
+            .user(Some(username))
+            .pass(Some(password))
+            .ssl_opts(ssl_opts)
+            .init(timeouts::mysql_session_settings());
+
+        let entry = db_config.username.clone();
+        let conn = Conn::new(opts).await.map_err(Error::mysql(
+            &entry,
+            &format!("connecting to {}:{}", host, port),
+        ))?;
+        Ok(MySql {
+            conn,
+            host,
+            port,
+            ssl_mode,
+            entry,
+        })
+    }
+
+    async fn execute(&mut self, stage: &str, statement: String) -> Result<(), Error> {
+        let operation = timing::sql_operation(&statement);
+        timing::timed_sql(
+            &statement,
+            timeouts::limit(&operation, self.conn.query_drop(&statement)),
+        )
+        .await
+        .unwrap_or_else(|timeout| Err(mysql_async::Error::Other(Box::new(timeout))))
+        .map_err(Error::mysql(&self.entry, stage))
+    }
+
+    async fn exists(&mut self, stage: &str, query: &str, name: &str) -> Result<bool, Error> {
+        let operation = timing::sql_operation(query);
+        let row: Option<u8> = timing::timed_sql(
+            query,
+            timeouts::limit(&operation, self.conn.exec_first(query, (name,))),
+        )
+        .await
+        .unwrap_or_else(|timeout| Err(mysql_async::Error::Other(Box::new(timeout))))
+        .map_err(Error::mysql(&self.entry, stage))?;
+        Ok(row.is_some())
+    }
+}
+
+impl Provisioner for MySql {
+    async fn user_exists(&mut self, username: &str) -> Result<bool, Error> {
+        self.exists(
+            "checking user",
+            "SELECT 1 FROM mysql.user WHERE User = ? AND Host = '%'",
+            username,
+        )
+        .await
+    }
+
+    async fn create_user(&mut self, username: &str, password: &str) -> Result<(), Error> {
+        self.execute(
+            "creating user",
+            format!(
+                "CREATE USER {} IDENTIFIED BY {}",
+                account(username),
+                quote_literal(password)
+            ),
+        )
+        .await
+    }
+
+    async fn set_password(&mut self, username: &str, password: &str) -> Result<(), Error> {
+        self.execute(
+            "setting password",
+            format!(
+                "ALTER USER {} IDENTIFIED BY {}",
+                account(username),
+                quote_literal(password)
+            ),
+        )
+        .await
+    }
+
+    async fn drop_user(&mut self, username: &str) -> Result<(), Error> {
+        self.execute(
+            "dropping user",
+            format!("DROP USER IF EXISTS {}", account(username)),
+        )
+        .await
+    }
+
+    async fn ensure_database(&mut self, database: &str) -> Result<bool, Error> {
+        let stage = format!("creating database {}", database);
+        if self
+            .exists(
+                &stage,
+                "SELECT 1 FROM information_schema.schemata WHERE schema_name = ?",
+                database,
+            )
+            .await?
+        {
+            return Ok(false);
+        }
+        self.execute(
+            &stage,
+            format!("CREATE DATABASE {}", quote_identifier(database)),
+        )
+        .await?;
+        Ok(true)
+    }
+
+    async fn drop_database(&mut self, database: &str) -> Result<(), Error> {
+        self.execute(
+            &format!("dropping database {}", database),
+            format!("DROP DATABASE IF EXISTS {}", quote_identifier(database)),
+        )
+        .await
+    }
+
+    async fn grant(
+        &mut self,
+        username: &str,
+        database: &str,
+        privileges: Option<&[String]>,
+    ) -> Result<(), Error> {
+        let privileges = privileges
+            .map(|privileges| privileges.join(", "))
+            .unwrap_or_else(|| "ALL PRIVILEGES".to_string());
+        self.execute(
+            &format!("granting access to {}", database),
+            format!(
+                "GRANT {} ON {}.* TO {}",
+                privileges,
+                quote_identifier(database),
+                account(username)
+            ),
+        )
+        .await
+    }
+
+    async fn disconnect(self) {
+        let _ = self.conn.disconnect().await;
+    }
+
+    fn secret_values(
+        &self,
+        db_config: &DatabaseConfig,
+        password: &str,
+    ) -> BTreeMap<String, String> {
+        let mut values = BTreeMap::from([
+            ("database_host".to_string(), self.host.clone()),
+            ("database_port".to_string(), self.port.to_string()),
+            ("username".to_string(), db_config.username.clone()),
+            ("password".to_string(), password.to_string()),
+        ]);
+        for (i, db) in db_config.databases.iter().enumerate() {
+            values.insert(format!("database.{}", i), db.clone());
+            values.insert(
+                format!("database_uri.{}", i),
+                format!(
+                    "mysql://{}:{}@{}:{}/{}",
+                    percent_encode(&db_config.username),
+                    percent_encode(password),
+                    uri_host(&self.host),
+                    self.port,
+                    percent_encode(db)
+                ),
+            );
+        }
+
+        match &db_config.secret_template {
+            Some(template) => {
+                values.insert("sslmode".to_string(), self.ssl_mode.clone());
+                template.render(&values)
+            }
+            None => values,
+        }
+    }
+}
+
+fn account(username: &str) -> String {
+    format!("{}@'%'", quote_literal(username))
+}
+
+// escapes for the default sql_mode, where backslashes escape inside literals
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "''"))
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("`{}`", name.replace('`', "``"))
+}
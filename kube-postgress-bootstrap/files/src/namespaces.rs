@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::Namespace;
+use kube::{
+    api::{Api, ListParams},
+    runtime::watcher,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    health, inventory, reconcile_entry, reload, report, sanitize_identifier, shutdown, DBClients,
+    DBConnection, DatabaseConfig, ServerConnections,
+};
+
+// comma separated databases to provision for the namespace
+const DATABASES_ANNOTATION: &str = "postgres-bootstrap/databases";
+
+// watches namespaces and provisions a user named after every namespace with the
+// databases annotation, with the credentials secret created in the namespace.
+// entries are only set up once, like config entries, an existing secret is kept.
+pub async fn watch(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+) -> anyhow::Result<()> {
+    let namespace_api: Api<Namespace> = Api::all(kube_client.clone());
+    let mut events = watcher(namespace_api.clone(), ListParams::default()).boxed();
+    info!(
+        "watching namespaces for the {} annotation",
+        DATABASES_ANNOTATION
+    );
+    let servers = ServerConnections::default();
+
+    loop {
+        let event = tokio::select! {
+            event = events.next() => match event {
+                Some(event) => event,
+                None => return Ok(()),
+            },
+            // relists every namespace, like a watch restart
+            _ = reload::wait() => namespace_api
+                .list(&ListParams::default())
+                .await
+                .map(|list| watcher::Event::Restarted(list.items))
+                .map_err(watcher::Error::InitialListFailed),
+            _ = shutdown::wait() => return Ok(()),
+        };
+        // a relist is a full cycle, a single namespace only counts when it has
+        // databases so unrelated namespaces don't hide a failed one
+        let (namespaces, mut cycle) = match event {
+            Ok(watcher::Event::Applied(namespace)) => (vec![namespace], false),
+            Ok(watcher::Event::Restarted(namespaces)) => (namespaces, true),
+            Ok(watcher::Event::Deleted(_)) => continue,
+            Err(e) => {
+                warn!("namespace watch failed, retrying: {}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+
+        let mut success = true;
+        for namespace in namespaces.iter() {
+            if shutdown::requested() {
+                return Ok(());
+            }
+            if let Some(db_config) = namespace_config(namespace) {
+                cycle = true;
+                // one broken namespace shouldn't stop provisioning for the others
+                let (_, result) = reconcile_entry(
+                    clients,
+                    kube_client,
+                    db_connection_details,
+                    &servers,
+                    "apply",
+                    db_config,
+                )
+                .await;
+                let error = match result {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some(format!("{:#}", e)),
+                    Err(panic) => Some(format!("panicked: {}", report::panic_message(&*panic))),
+                };
+                success &= error.is_none();
+                if let Some(error) = error {
+                    error!(
+                        "failed to provision namespace {}: {}",
+                        namespace.metadata.name.as_deref().unwrap_or_default(),
+                        error
+                    );
+                }
+            }
+        }
+        if let Err(e) = inventory::flush(kube_client).await {
+            warn!("failed to record inventory: {:#}", e);
+        }
+        if cycle {
+            health::cycle_finished(success);
+        }
+    }
+}
+
+fn namespace_config(namespace: &Namespace) -> Option<DatabaseConfig> {
+    let name = namespace.metadata.name.clone()?;
+    let databases: Vec<String> = namespace
+        .metadata
+        .annotations
+        .as_ref()?
+        .get(DATABASES_ANNOTATION)?
+        .split(',')
+        .map(str::trim)
+        .filter(|database| !database.is_empty())
+        .map(sanitize_identifier)
+        .collect();
+    if databases.is_empty() {
+        return None;
+    }
+
+    Some(DatabaseConfig {
+        username: sanitize_identifier(&name),
+        databases,
+        namespace: name,
+        ..Default::default()
+    })
+}
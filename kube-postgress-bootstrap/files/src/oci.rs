@@ -0,0 +1,261 @@
+use std::env;
+
+use anyhow::Context;
+use base64::Engine;
+use k8s_openapi::api::core::v1::Secret;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::{cosign, secret_value};
+
+const MANIFEST_MEDIA_TYPES: &str =
+    "application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+
+// an oci://registry/repository:tag (or @sha256:...) reference
+struct Reference {
+    registry: String,
+    repository: String,
+    reference: String,
+}
+
+fn parse_reference(location: &str) -> anyhow::Result<Reference> {
+    let path = location
+        .strip_prefix("oci://")
+        .context("oci locations must start with oci://")?;
+    let (registry, repository) = path.split_once('/').with_context(|| {
+        format!(
+            "oci location must be oci://registry/repository:tag, got {}",
+            location
+        )
+    })?;
+
+    let (repository, reference) = match repository.split_once('@') {
+        Some((repository, digest)) => (repository, digest),
+        None => match repository.rsplit_once(':') {
+            Some((repository, tag)) => (repository, tag),
+            None => (repository, "latest"),
+        },
+    };
+
+    Ok(Reference {
+        registry: registry.to_string(),
+        repository: repository.to_string(),
+        reference: reference.to_string(),
+    })
+}
+
+// an authenticated session against a single repository
+struct Registry {
+    http: reqwest::Client,
+    base: String,
+    token: Option<String>,
+    credentials: Option<(String, String)>,
+}
+
+impl Registry {
+    async fn connect(reference: &Reference) -> anyhow::Result<Registry> {
+        let credentials = registry_credentials(&reference.registry).await?;
+        let http = reqwest::Client::new();
+        let base = format!("https://{}/v2/{}", reference.registry, reference.repository);
+        let token = auth_token(
+            &http,
+            &format!("{}/manifests/{}", base, reference.reference),
+            credentials.as_ref(),
+        )
+        .await?;
+
+        Ok(Registry {
+            http,
+            base,
+            token,
+            credentials,
+        })
+    }
+
+    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        let request = self.http.get(format!("{}/{}", self.base, path));
+        match (&self.token, &self.credentials) {
+            (Some(token), _) => request.bearer_auth(token),
+            (None, Some((username, password))) => request.basic_auth(username, Some(password)),
+            (None, None) => request,
+        }
+    }
+
+    // returns the manifest along with its digest
+    async fn manifest(&self, reference: &str) -> anyhow::Result<(serde_json::Value, String)> {
+        let manifest = self
+            .get(&format!("manifests/{}", reference))
+            .header("Accept", MANIFEST_MEDIA_TYPES)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        Ok((
+            serde_json::from_slice(&manifest)?,
+            format!("sha256:{:x}", Sha256::digest(&manifest)),
+        ))
+    }
+
+    async fn blob(&self, digest: &str) -> anyhow::Result<Vec<u8>> {
+        let blob = self
+            .get(&format!("blobs/{}", digest))
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?
+            .to_vec();
+
+        let expected = digest.strip_prefix("sha256:").unwrap_or(digest);
+        if format!("{:x}", Sha256::digest(&blob)) != expected {
+            anyhow::bail!("blob {} does not match its digest", digest);
+        }
+        Ok(blob)
+    }
+
+    // cosign stores signatures under the sha256-<digest>.sig tag, as layers holding
+    // a simple signing payload that names the signed manifest digest
+    async fn verify_signature(
+        &self,
+        digest: &str,
+        public_key: &p256::ecdsa::VerifyingKey,
+    ) -> anyhow::Result<()> {
+        let (signatures, _) = self
+            .manifest(&format!("{}.sig", digest.replace(':', "-")))
+            .await
+            .context("failed to fetch cosign signatures")?;
+
+        for layer in signatures["layers"].as_array().into_iter().flatten() {
+            let (Some(layer_digest), Some(signature)) = (
+                layer["digest"].as_str(),
+                layer["annotations"]["dev.cosignproject.cosign/signature"].as_str(),
+            ) else {
+                continue;
+            };
+            let payload = self.blob(layer_digest).await?;
+            if cosign::verify(public_key, &payload, signature.as_bytes()).is_err() {
+                continue;
+            }
+
+            let payload: serde_json::Value = serde_json::from_slice(&payload)?;
+            if payload["critical"]["image"]["docker-manifest-digest"].as_str() == Some(digest) {
+                return Ok(());
+            }
+        }
+
+        anyhow::bail!("no valid cosign signature for {}", digest)
+    }
+}
+
+// pulls the first layer of an oci artifact, e.g. one pushed with
+// `oras push registry/team/db-config:tag config.json`. registry credentials come
+// from the dockerconfigjson secret in OCI_CREDENTIALS_SECRET (namespace/name).
+// with a public key the manifest must carry a matching cosign signature.
+pub async fn read_artifact(
+    location: &str,
+    public_key: Option<&p256::ecdsa::VerifyingKey>,
+) -> anyhow::Result<Vec<u8>> {
+    let reference = parse_reference(location)?;
+    let registry = Registry::connect(&reference).await?;
+
+    let (manifest, digest) = registry.manifest(&reference.reference).await?;
+    if let Some(public_key) = public_key {
+        registry.verify_signature(&digest, public_key).await?;
+        info!("verified cosign signature of {}", location);
+    }
+
+    let layer = manifest["layers"][0]["digest"]
+        .as_str()
+        .with_context(|| format!("oci artifact {} has no layers", location))?;
+    registry.blob(layer).await
+}
+
+// registries answer anonymous requests with a 401 pointing at their token
+// service, which hands out a bearer token for the repository
+async fn auth_token(
+    http: &reqwest::Client,
+    url: &str,
+    credentials: Option<&(String, String)>,
+) -> anyhow::Result<Option<String>> {
+    let response = http
+        .head(url)
+        .header("Accept", MANIFEST_MEDIA_TYPES)
+        .send()
+        .await?;
+    if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+        return Ok(None);
+    }
+
+    let challenge = response
+        .headers()
+        .get("www-authenticate")
+        .and_then(|header| header.to_str().ok())
+        .unwrap_or_default();
+    let challenge = match challenge.strip_prefix("Bearer ") {
+        Some(challenge) => challenge,
+        // basic auth registries take the credentials on every request
+        None => return Ok(None),
+    };
+
+    let mut realm = None;
+    let mut query = Vec::new();
+    for param in challenge.split(',') {
+        if let Some((name, value)) = param.trim().split_once('=') {
+            let value = value.trim_matches('"').to_string();
+            match name {
+                "realm" => realm = Some(value),
+                name => query.push((name.to_string(), value)),
+            }
+        }
+    }
+
+    let mut request = http
+        .get(realm.context("registry auth challenge has no realm")?)
+        .query(&query);
+    if let Some((username, password)) = credentials {
+        request = request.basic_auth(username, Some(password));
+    }
+    let token: serde_json::Value = request.send().await?.error_for_status()?.json().await?;
+
+    Ok(token["token"]
+        .as_str()
+        .or_else(|| token["access_token"].as_str())
+        .map(str::to_string))
+}
+
+async fn registry_credentials(registry: &str) -> anyhow::Result<Option<(String, String)>> {
+    let secret_ref = match env::var("OCI_CREDENTIALS_SECRET") {
+        Ok(secret_ref) => secret_ref,
+        Err(_) => return Ok(None),
+    };
+    let (namespace, name) = secret_ref
+        .split_once('/')
+        .context("OCI_CREDENTIALS_SECRET must be formatted as namespace/name")?;
+
+    let kube_client = kube::Client::try_from(crate::kube_config::load().await?)?;
+    let secrets: kube::api::Api<Secret> = kube::api::Api::namespaced(kube_client, namespace);
+    let secret = secrets.get(name).await?;
+    let docker_config: serde_json::Value = serde_json::from_str(
+        &secret_value(&secret, ".dockerconfigjson")
+            .with_context(|| format!("secret {} has no .dockerconfigjson", secret_ref))?,
+    )?;
+
+    let auth = &docker_config["auths"][registry];
+    if let (Some(username), Some(password)) = (auth["username"].as_str(), auth["password"].as_str())
+    {
+        return Ok(Some((username.to_string(), password.to_string())));
+    }
+    match auth["auth"].as_str() {
+        Some(encoded) => {
+            let decoded =
+                String::from_utf8(base64::engine::general_purpose::STANDARD.decode(encoded)?)?;
+            let (username, password) = decoded
+                .split_once(':')
+                .context("registry auth must be username:password")?;
+            Ok(Some((username.to_string(), password.to_string())))
+        }
+        None => anyhow::bail!("secret {} has no credentials for {}", secret_ref, registry),
+    }
+}
@@ -0,0 +1,385 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use k8s_openapi::chrono::{SecondsFormat, Utc};
+use kube::{
+    api::{Api, ApiResource, DynamicObject, GroupVersionKind, ListParams, Patch, PatchParams},
+    runtime::watcher,
+    ResourceExt,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    cli::NamespaceOverrides, env_number, error::ConfigError, health, inventory, parse_document,
+    prepare_configs, reconcile_entries, reload, report::Report, shutdown, DBClients, DBConnection,
+    DatabaseConfig, Error, ServerConnections,
+};
+
+// runs as a controller for postgres-bootstrap.io/v1alpha1 DatabaseBootstrap
+// resources, see `operator --print-crd`. the spec of each resource is a config
+// entry in the resource's namespace, see tenant_configs. the Ready condition in
+// its status reports the last reconcile. every OPERATOR_RESYNC_SECONDS (defaults to 300) all resources are
+// reconciled again so drifted passwords are repaired.
+pub async fn run(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+) -> anyhow::Result<()> {
+    let gvk = GroupVersionKind::gvk("postgres-bootstrap.io", "v1alpha1", "DatabaseBootstrap");
+    let resource = ApiResource::from_gvk_with_plural(&gvk, "databasebootstraps");
+    let bootstraps: Api<DynamicObject> = Api::all_with(kube_client.clone(), &resource);
+    let resync = Duration::from_secs(env_number("OPERATOR_RESYNC_SECONDS")?.unwrap_or(300));
+
+    let mut events = watcher(bootstraps.clone(), ListParams::default()).boxed();
+    let mut resync_interval = tokio::time::interval(resync);
+    // the first tick completes immediately, the initial list comes from the watcher
+    resync_interval.tick().await;
+    info!("watching DatabaseBootstrap resources");
+    let servers = ServerConnections::default();
+
+    loop {
+        let objects = tokio::select! {
+            _ = shutdown::wait() => return Ok(()),
+            event = events.next() => match event {
+                Some(Ok(watcher::Event::Applied(object))) if is_reconciled(&object) => continue,
+                Some(Ok(watcher::Event::Applied(object))) => vec![object],
+                Some(Ok(watcher::Event::Restarted(objects))) => objects,
+                Some(Ok(watcher::Event::Deleted(object))) => {
+                    // like entries removed from a config, the user and databases are kept
+                    info!("DatabaseBootstrap {} deleted, leaving its user in place", object.name_any());
+                    continue;
+                }
+                Some(Err(e)) => {
+                    warn!("DatabaseBootstrap watch failed, retrying: {}", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+                None => return Ok(()),
+            },
+            _ = resync_interval.tick() => list(&bootstraps).await,
+            _ = reload::wait() => list(&bootstraps).await,
+        };
+
+        let mut success = true;
+        for object in objects.iter() {
+            if shutdown::requested() {
+                return Ok(());
+            }
+            let result = reconcile(
+                clients,
+                kube_client,
+                db_connection_details,
+                &servers,
+                object,
+            )
+            .await;
+            success &= result.is_ok();
+            if let Err(e) = &result {
+                error!(
+                    "failed to reconcile DatabaseBootstrap {}: {:#}",
+                    object.name_any(),
+                    e
+                );
+            }
+            if let Err(e) = update_status(&resource, kube_client, object, &result).await {
+                warn!(
+                    "failed to update status of DatabaseBootstrap {}: {:#}",
+                    object.name_any(),
+                    e
+                );
+            }
+        }
+        if let Err(e) = inventory::flush(kube_client).await {
+            warn!("failed to record inventory: {:#}", e);
+        }
+        health::cycle_finished(success);
+    }
+}
+
+// a failed list is retried at the next resync, the watch keeps running meanwhile
+async fn list(bootstraps: &Api<DynamicObject>) -> Vec<DynamicObject> {
+    match bootstraps.list(&ListParams::default()).await {
+        Ok(list) => list.items,
+        Err(e) => {
+            warn!("failed to list DatabaseBootstrap resources: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+async fn reconcile(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    servers: &ServerConnections,
+    object: &DynamicObject,
+) -> Result<(), Error> {
+    let db_configs = tenant_configs(object)?;
+
+    let mut report = Report::default();
+    reconcile_entries(
+        clients,
+        kube_client,
+        db_connection_details,
+        servers,
+        "repair",
+        db_configs,
+        &mut report,
+    )
+    .await;
+    match report.errors() {
+        errors if errors.is_empty() => Ok(()),
+        errors => Err(Error::Other(anyhow::anyhow!(errors.join("; ")))),
+    }
+}
+
+// the spec goes through the same checks and expansion as an entry of a config
+// file, grantsOn can reference the entries of the same resource. whoever can
+// create a resource in a namespace writes its spec, so the entry is always put in
+// that namespace and can't reach beyond it: other namespaces, other servers and
+// their admin credentials, and sql run as the admin are left to config files.
+fn tenant_configs(object: &DynamicObject) -> Result<Vec<DatabaseConfig>, Error> {
+    let namespace = object.namespace().unwrap_or_default();
+    let mut spec = object.data.get("spec").cloned().unwrap_or_default();
+    if let Some(spec) = spec.as_object_mut() {
+        spec.insert(
+            "namespace".to_string(),
+            serde_json::Value::from(namespace.clone()),
+        );
+    }
+    let entries = parse_document(serde_json::Value::Array(vec![spec]))?;
+    let db_configs = prepare_configs(entries, &NamespaceOverrides::default())?;
+    for db_config in db_configs.iter() {
+        check_tenant(&namespace, db_config)?;
+    }
+    Ok(db_configs)
+}
+
+fn check_tenant(namespace: &str, db_config: &DatabaseConfig) -> Result<(), ConfigError> {
+    let other_namespace = |other: Option<&String>| other.is_some_and(|other| other != namespace);
+    let mut rejected = Vec::new();
+    if !db_config.extra_namespaces.is_empty() {
+        rejected.push("extraNamespaces");
+    }
+    if other_namespace(
+        db_config
+            .password_secret_ref
+            .as_ref()
+            .and_then(|secret_ref| secret_ref.namespace.as_ref()),
+    ) {
+        rejected.push("passwordSecretRef in another namespace");
+    }
+    if other_namespace(
+        db_config
+            .grafana_datasource
+            .as_ref()
+            .map(|datasource| datasource.secret_ref(namespace, &db_config.username).0)
+            .as_ref(),
+    ) {
+        rejected.push("grafanaDatasource in another namespace");
+    }
+    if db_config.host.is_some() {
+        rejected.push("host");
+    }
+    if db_config.port.is_some() {
+        rejected.push("port");
+    }
+    if db_config.admin_secret.is_some() {
+        rejected.push("adminSecret");
+    }
+    let options = db_config.database_options.values();
+    if options.clone().any(|options| !options.init_sql.is_empty()) {
+        rejected.push("initSql");
+    }
+    if options.clone().any(|options| options.seed.is_some()) {
+        rejected.push("seed");
+    }
+    if rejected.is_empty() {
+        return Ok(());
+    }
+    Err(ConfigError::Invalid {
+        entry: db_config.username.clone(),
+        message: format!(
+            "a DatabaseBootstrap can't set {}, that takes a config file",
+            rejected.join(", ")
+        ),
+    })
+}
+
+// the spec is validated against the config schema by the controller, so the
+// crd accepts any fields
+pub fn crd() -> serde_json::Value {
+    let object = serde_json::json!({
+        "type": "object",
+        "x-kubernetes-preserve-unknown-fields": true,
+    });
+    serde_json::json!({
+        "apiVersion": "apiextensions.k8s.io/v1",
+        "kind": "CustomResourceDefinition",
+        "metadata": { "name": "databasebootstraps.postgres-bootstrap.io" },
+        "spec": {
+            "group": "postgres-bootstrap.io",
+            "scope": "Namespaced",
+            "names": {
+                "kind": "DatabaseBootstrap",
+                "listKind": "DatabaseBootstrapList",
+                "plural": "databasebootstraps",
+                "singular": "databasebootstrap",
+            },
+            "versions": [{
+                "name": "v1alpha1",
+                "served": true,
+                "storage": true,
+                "subresources": { "status": {} },
+                "additionalPrinterColumns": [{
+                    "name": "Ready",
+                    "type": "string",
+                    "jsonPath": ".status.conditions[?(@.type==\"Ready\")].status",
+                }],
+                "schema": {
+                    "openAPIV3Schema": {
+                        "type": "object",
+                        "properties": { "spec": object, "status": object },
+                    },
+                },
+            }],
+        },
+    })
+}
+
+// skips the update events caused by our own status patches
+fn is_reconciled(object: &DynamicObject) -> bool {
+    let status = &object.data["status"];
+    status["observedGeneration"].as_i64() == object.metadata.generation
+        && status["conditions"][0]["status"] == "True"
+}
+
+async fn update_status(
+    resource: &ApiResource,
+    kube_client: &kube::Client,
+    object: &DynamicObject,
+    result: &Result<(), Error>,
+) -> anyhow::Result<()> {
+    let (status, reason, message) = match result {
+        Ok(()) => (
+            "True",
+            "Reconciled",
+            "user, databases and secret are up to date".to_string(),
+        ),
+        Err(e) => ("False", "ReconcileFailed", format!("{:#}", e)),
+    };
+    // the transition time only moves when the condition changes
+    let previous = &object.data["status"]["conditions"][0];
+    let last_transition_time = match previous["lastTransitionTime"].as_str() {
+        Some(time) if previous["status"] == status => time.to_string(),
+        _ => Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+    };
+
+    let bootstraps: Api<DynamicObject> = Api::namespaced_with(
+        kube_client.clone(),
+        &object.namespace().unwrap_or_default(),
+        resource,
+    );
+    bootstraps
+        .patch_status(
+            &object.name_any(),
+            &PatchParams::default(),
+            &Patch::Merge(serde_json::json!({
+                "status": {
+                    "observedGeneration": object.metadata.generation,
+                    "conditions": [{
+                        "type": "Ready",
+                        "status": status,
+                        "reason": reason,
+                        "message": message,
+                        "lastTransitionTime": last_transition_time,
+                    }],
+                },
+            })),
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bootstrap(spec: serde_json::Value) -> DynamicObject {
+        serde_json::from_value(serde_json::json!({
+            "apiVersion": "postgres-bootstrap.io/v1alpha1",
+            "kind": "DatabaseBootstrap",
+            "metadata": { "name": "app", "namespace": "team-a" },
+            "spec": spec,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn entries_are_put_in_the_resources_namespace() {
+        let object = bootstrap(serde_json::json!({
+            "username": "app",
+            "namespace": "kube-system",
+            "databases": ["app"],
+        }));
+        let db_configs = tenant_configs(&object).unwrap();
+        assert_eq!(db_configs[0].namespace, "team-a");
+    }
+
+    #[test]
+    fn hostile_specs_are_rejected() {
+        let hostile = [
+            serde_json::json!({ "extraNamespaces": ["kube-system"] }),
+            serde_json::json!({
+                "passwordSecretRef": { "name": "admin", "namespace": "kube-system" },
+            }),
+            serde_json::json!({ "host": "attacker.example.com" }),
+            serde_json::json!({ "port": 5433 }),
+            serde_json::json!({ "adminSecret": "kube-system/postgres-admin" }),
+            serde_json::json!({
+                "databaseOptions": { "app": { "initSql": ["ALTER ROLE app SUPERUSER"] } },
+            }),
+            serde_json::json!({ "databaseOptions": { "app": { "seed": "/etc/passwd" } } }),
+        ];
+        for overrides in hostile.into_iter() {
+            let mut spec = serde_json::json!({ "username": "app", "databases": ["app"] });
+            for (key, value) in overrides.as_object().unwrap().iter() {
+                spec[key] = value.clone();
+            }
+            let object = bootstrap(spec.clone());
+            assert!(
+                matches!(
+                    tenant_configs(&object),
+                    Err(Error::Config(ConfigError::Invalid { .. }))
+                ),
+                "{} was accepted",
+                spec
+            );
+        }
+    }
+
+    #[test]
+    fn variables_are_not_expanded() {
+        std::env::set_var("OPERATOR_TEST_SECRET", "hunter2");
+        let object = bootstrap(serde_json::json!({
+            "username": "app",
+            "databases": ["app"],
+            "secretLabels": { "leak": "${OPERATOR_TEST_SECRET}" },
+        }));
+        let db_configs = tenant_configs(&object).unwrap();
+        assert_eq!(
+            db_configs[0].secret_labels["leak"],
+            "${OPERATOR_TEST_SECRET}"
+        );
+    }
+
+    #[test]
+    fn refs_within_the_namespace_are_allowed() {
+        let object = bootstrap(serde_json::json!({
+            "username": "app",
+            "databases": ["app"],
+            "passwordSecretRef": { "name": "app-password", "namespace": "team-a" },
+        }));
+        assert!(tenant_configs(&object).is_ok());
+    }
+}
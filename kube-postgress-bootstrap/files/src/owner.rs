@@ -0,0 +1,58 @@
+use anyhow::Context;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::{
+    api::{Api, DynamicObject, GroupVersionKind},
+    discovery::Scope,
+};
+
+// an object owning the entry's secret, e.g. the app's Deployment or custom
+// resource, so kubernetes garbage collects the secret along with it. namespaced
+// owners have to be in the entry's namespace.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretOwnerConfig {
+    api_version: String,
+    kind: String,
+    name: String,
+    // keep the owner around until the secret is deleted when deleting in the
+    // foreground
+    #[serde(default)]
+    block_owner_deletion: bool,
+}
+
+// looks the owner up for its uid, which the reference has to carry
+pub async fn owner_reference(
+    kube_client: &kube::Client,
+    namespace: &str,
+    owner: &SecretOwnerConfig,
+) -> anyhow::Result<OwnerReference> {
+    let (group, version) = owner
+        .api_version
+        .split_once('/')
+        .unwrap_or(("", owner.api_version.as_str()));
+    let gvk = GroupVersionKind::gvk(group, version, &owner.kind);
+    let (resource, capabilities) = kube::discovery::pinned_kind(kube_client, &gvk)
+        .await
+        .with_context(|| format!("unknown owner kind {} {}", owner.api_version, owner.kind))?;
+
+    let objects: Api<DynamicObject> = match capabilities.scope {
+        Scope::Cluster => Api::all_with(kube_client.clone(), &resource),
+        Scope::Namespaced => Api::namespaced_with(kube_client.clone(), namespace, &resource),
+    };
+    let object = objects
+        .get(&owner.name)
+        .await
+        .with_context(|| format!("failed to get owner {} {}", owner.kind, owner.name))?;
+
+    Ok(OwnerReference {
+        api_version: owner.api_version.clone(),
+        kind: owner.kind.clone(),
+        name: owner.name.clone(),
+        uid: object
+            .metadata
+            .uid
+            .with_context(|| format!("owner {} {} has no uid", owner.kind, owner.name))?,
+        block_owner_deletion: Some(owner.block_owner_deletion),
+        controller: None,
+    })
+}
@@ -0,0 +1,210 @@
+use std::{env, sync::OnceLock};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::ConfigError;
+
+const LOWERCASE: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &[u8] = b"0123456789";
+// no quotes, backslashes, slashes or spaces, which break connection strings and
+// shell snippets
+const SYMBOLS: &[u8] = b"!#$%&()*+,-.:;<=>?@[]^_{|}~";
+// the unreserved characters of rfc 3986, which never need escaping in a url
+const URL_SAFE_SYMBOLS: &[u8] = b"-._~";
+
+const CONSONANTS: &[u8] = b"bcdfghjklmnprstvwz";
+const VOWELS: &[u8] = b"aeiou";
+
+// how generated passwords look, set per entry with passwordPolicy or for every
+// entry with PASSWORD_POLICY (the same object as json). defaults to 20
+// alphanumeric characters.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PasswordPolicy {
+    pub length: usize,
+    // also use symbols, each enabled class appears at least once
+    pub symbols: bool,
+    // limits symbols to -._~ so the password can be put in a url as is
+    pub url_safe: bool,
+    // generate this many pronounceable words joined by "-" instead, length is
+    // ignored. every word has three syllables, about 20 bits each.
+    pub passphrase_words: Option<usize>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            length: 20,
+            symbols: false,
+            url_safe: false,
+            passphrase_words: None,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.passphrase_words {
+            Some(words) if words < 3 => {
+                Err(format!("passphrases need at least 3 words, got {}", words))
+            }
+            None if self.length < 8 => Err(format!(
+                "passwords need at least 8 characters, got {}",
+                self.length
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn generate(&self) -> String {
+        if let Some(words) = self.passphrase_words {
+            return (0..words).map(|_| word()).collect::<Vec<_>>().join("-");
+        }
+
+        let mut classes = vec![LOWERCASE, UPPERCASE, DIGITS];
+        match (self.symbols, self.url_safe) {
+            (true, true) => classes.push(URL_SAFE_SYMBOLS),
+            (true, false) => classes.push(SYMBOLS),
+            (false, _) => {}
+        }
+        let charset = classes.concat();
+
+        let mut rng = rand::thread_rng();
+        // one character of every class, the rest from all of them, then shuffled
+        // so the guaranteed ones aren't always up front
+        let mut password: Vec<u8> = classes
+            .iter()
+            .map(|class| *class.choose(&mut rng).unwrap())
+            .collect();
+        while password.len() < self.length {
+            password.push(*charset.choose(&mut rng).unwrap());
+        }
+        password.shuffle(&mut rng);
+        String::from_utf8(password).unwrap()
+    }
+}
+
+fn word() -> String {
+    let mut rng = rand::thread_rng();
+    (0..3)
+        .flat_map(|_| {
+            [
+                CONSONANTS[rng.gen_range(0..CONSONANTS.len())],
+                VOWELS[rng.gen_range(0..VOWELS.len())],
+            ]
+        })
+        .map(char::from)
+        .collect()
+}
+
+static GLOBAL_POLICY: OnceLock<PasswordPolicy> = OnceLock::new();
+
+// reads PASSWORD_POLICY, before any password is generated
+pub fn configure() -> Result<(), ConfigError> {
+    let policy = match env::var("PASSWORD_POLICY") {
+        Ok(policy) => {
+            let policy: PasswordPolicy = serde_json::from_str(&policy).map_err(|e| {
+                ConfigError::setting("PASSWORD_POLICY", format!("not a valid policy: {}", e))
+            })?;
+            policy
+                .validate()
+                .map_err(|e| ConfigError::setting("PASSWORD_POLICY", e))?;
+            policy
+        }
+        Err(_) => PasswordPolicy::default(),
+    };
+    let _ = GLOBAL_POLICY.set(policy);
+    Ok(())
+}
+
+fn global_policy() -> &'static PasswordPolicy {
+    GLOBAL_POLICY.get_or_init(PasswordPolicy::default)
+}
+
+// the entry's policy, falling back to PASSWORD_POLICY
+pub fn generate(policy: Option<&PasswordPolicy>) -> String {
+    match policy {
+        Some(policy) => policy.generate(),
+        None => global_policy().generate(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(length: usize, symbols: bool, url_safe: bool) -> PasswordPolicy {
+        PasswordPolicy {
+            length,
+            symbols,
+            url_safe,
+            passphrase_words: None,
+        }
+    }
+
+    #[test]
+    fn default_is_alphanumeric() {
+        for _ in 0..50 {
+            let password = PasswordPolicy::default().generate();
+            assert_eq!(password.len(), 20);
+            assert!(password.bytes().all(|b| b.is_ascii_alphanumeric()));
+            assert!(password.bytes().any(|b| b.is_ascii_lowercase()));
+            assert!(password.bytes().any(|b| b.is_ascii_uppercase()));
+            assert!(password.bytes().any(|b| b.is_ascii_digit()));
+        }
+    }
+
+    #[test]
+    fn symbols_appear_at_least_once() {
+        for _ in 0..50 {
+            let password = policy(8, true, false).generate();
+            assert_eq!(password.len(), 8);
+            assert!(password.bytes().any(|b| SYMBOLS.contains(&b)));
+            assert!(!password.contains(['\'', '"', '\\', '/', ' ']));
+        }
+    }
+
+    #[test]
+    fn url_safe_symbols() {
+        for _ in 0..50 {
+            let password = policy(32, true, true).generate();
+            assert!(password
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || URL_SAFE_SYMBOLS.contains(&b)));
+            assert!(password.bytes().any(|b| URL_SAFE_SYMBOLS.contains(&b)));
+        }
+    }
+
+    #[test]
+    fn passphrases() {
+        let policy = PasswordPolicy {
+            passphrase_words: Some(4),
+            ..Default::default()
+        };
+        let passphrase = policy.generate();
+        let words: Vec<&str> = passphrase.split('-').collect();
+        assert_eq!(words.len(), 4);
+        assert!(words
+            .iter()
+            .all(|word| word.len() == 6 && word.bytes().all(|b| b.is_ascii_lowercase())));
+    }
+
+    #[test]
+    fn validate_rejects_weak_policies() {
+        assert!(PasswordPolicy::default().validate().is_ok());
+        assert!(policy(8, false, false).validate().is_ok());
+        assert!(policy(7, false, false).validate().is_err());
+        let short_passphrase = PasswordPolicy {
+            passphrase_words: Some(2),
+            ..Default::default()
+        };
+        assert!(short_passphrase.validate().is_err());
+    }
+
+    #[test]
+    fn generate_prefers_the_entry_policy() {
+        let password = generate(Some(&policy(40, false, false)));
+        assert_eq!(password.len(), 40);
+    }
+}
@@ -0,0 +1,388 @@
+use k8s_openapi::api::core::v1::Secret;
+use kube::error::ErrorResponse;
+
+use crate::{
+    config_map_name, create_database_statement, csi, database_privilege_statements,
+    provisioner::Engine,
+    quote_identifier,
+    retry::{retry, transient_kube},
+    schema_privilege_statements, secret_name,
+    timing::TimedClient,
+    DBClients, DatabaseConfig, DatabaseOptions, DATABASE_PRIVILEGES,
+};
+
+// a single change the bootstrap would make for an entry, with the sql it would run
+#[derive(serde::Serialize)]
+struct PlannedChange {
+    username: String,
+    action: &'static str,
+    resource: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    statements: Vec<String>,
+}
+
+// prints what applying the config would change without touching anything.
+// --output=markdown renders a table for posting as a pull request comment,
+// --output=json the changes for diffing in ci.
+pub async fn run(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_configs: &[DatabaseConfig],
+    output: &str,
+) -> anyhow::Result<()> {
+    let mut changes = Vec::new();
+    for db_config in db_configs.iter() {
+        changes.extend(plan_entry(clients, kube_client, db_config).await?);
+    }
+
+    match output {
+        "text" => print_text(&changes),
+        "markdown" => print_markdown(&changes),
+        "json" => print_json(&changes)?,
+        output => anyhow::bail!(
+            "unknown plan output {}, expected text, markdown or json",
+            output
+        ),
+    }
+    Ok(())
+}
+
+async fn plan_entry(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_config: &DatabaseConfig,
+) -> anyhow::Result<Vec<PlannedChange>> {
+    let change = |action, resource: String| PlannedChange {
+        username: db_config.username.clone(),
+        action,
+        resource,
+        statements: Vec::new(),
+    };
+    let secret_name = secret_name(db_config);
+    let secret_resource = match &db_config.csi {
+        Some(_) => format!(
+            "SecretProviderClass {}/{}",
+            db_config.namespace, secret_name
+        ),
+        None => format!("Secret {}/{}", db_config.namespace, secret_name),
+    };
+
+    // planning entries of other engines would take a connection to their server
+    if db_config.engine != Engine::Postgres {
+        return Ok(vec![
+            change(
+                "apply",
+                format!(
+                    "{} user {} with databases {}",
+                    db_config.engine.as_str(),
+                    db_config.username,
+                    db_config.databases.join(", ")
+                ),
+            ),
+            change("apply", secret_resource),
+        ]);
+    }
+
+    let username = quote_identifier(&db_config.username);
+    // grantsOn is reconciled on every run, even for entries that are skipped
+    let mut changes: Vec<PlannedChange> = db_config
+        .grants_on
+        .iter()
+        .map(|grant| {
+            let mut grant_change = change(
+                "apply",
+                format!("grants on database {} of {}", grant.database, grant.entry),
+            );
+            let (database_privileges, table_privileges): (Vec<String>, Vec<String>) = grant
+                .privileges
+                .iter()
+                .map(|privilege| privilege.to_uppercase())
+                .partition(|privilege| DATABASE_PRIVILEGES.contains(&privilege.as_str()));
+            if !database_privileges.is_empty() {
+                grant_change.statements.push(format!(
+                    "GRANT {} ON DATABASE {} TO {}",
+                    database_privileges.join(", "),
+                    quote_identifier(&grant.database),
+                    username
+                ));
+            }
+            if !table_privileges.is_empty() {
+                for schema in grant.schemas.iter() {
+                    grant_change.statements.push(format!(
+                        "GRANT {} ON ALL TABLES IN SCHEMA {} TO {}",
+                        table_privileges.join(", "),
+                        quote_identifier(schema),
+                        username
+                    ));
+                }
+            }
+            grant_change
+        })
+        .collect();
+
+    if !db_config.manage_database() {
+        changes.push(change("apply", secret_resource));
+        return Ok(changes);
+    }
+
+    // entries with an existing secret keep its password, provider classes can't
+    // be updated at all
+    let secret_exists =
+        db_config.create_secret() && secret_exists(kube_client, db_config, &secret_name).await?;
+    if secret_exists && db_config.csi.is_some() {
+        changes.push(change("unchanged", secret_resource));
+        return Ok(changes);
+    }
+
+    let user_exists = !clients
+        .admin
+        .timed_query(
+            "SELECT 1 FROM pg_roles WHERE rolname = $1;",
+            &[&db_config.username],
+        )
+        .await?
+        .is_empty();
+    let mut user_change = change(
+        if user_exists { "update" } else { "create" },
+        format!("user {}", db_config.username),
+    );
+    // passwords are generated when applying, so they never show up in the plan
+    match (user_exists, db_config.create_secret(), secret_exists) {
+        _ if db_config.iam_auth => {
+            if !user_exists {
+                user_change
+                    .statements
+                    .push(format!("CREATE USER {}", username));
+            }
+            user_change.statements.extend([
+                format!("ALTER ROLE {} WITH PASSWORD NULL", username),
+                format!("GRANT rds_iam TO {}", username),
+            ]);
+        }
+        (false, true, true) => user_change.statements.push(format!(
+            "CREATE USER {} WITH PASSWORD '<from secret>'",
+            username
+        )),
+        (false, true, false) => user_change.statements.push(format!(
+            "CREATE USER {} WITH PASSWORD '<generated>'",
+            username
+        )),
+        (false, false, _) => user_change
+            .statements
+            .push(format!("CREATE USER {}", username)),
+        (true, true, false) => user_change.statements.push(format!(
+            "ALTER USER {} WITH PASSWORD '<generated>'",
+            username
+        )),
+        (true, _, _) => {}
+    }
+    if let Some(connection_limit) = db_config.connection_limit {
+        user_change.statements.push(format!(
+            "ALTER ROLE {} CONNECTION LIMIT {}",
+            username, connection_limit
+        ));
+    }
+    if let Some(statement) = db_config
+        .role_attributes
+        .as_ref()
+        .and_then(|attributes| attributes.alter_statement(&db_config.username))
+    {
+        user_change.statements.push(statement);
+    }
+    for group in db_config.member_of.iter() {
+        let group_exists = !clients
+            .admin
+            .timed_query("SELECT 1 FROM pg_roles WHERE rolname = $1", &[group])
+            .await?
+            .is_empty();
+        if !group_exists {
+            user_change
+                .statements
+                .push(format!("CREATE ROLE {} NOLOGIN", quote_identifier(group)));
+        }
+        user_change
+            .statements
+            .push(format!("GRANT {} TO {}", quote_identifier(group), username));
+    }
+    changes.push(user_change);
+
+    for database in db_config
+        .databases
+        .iter()
+        .filter(|_| db_config.provisions_databases())
+    {
+        let database_exists = !clients
+            .admin
+            .timed_query("SELECT 1 FROM pg_database WHERE datname = $1;", &[database])
+            .await?
+            .is_empty();
+        let mut database_change = change(
+            if database_exists { "update" } else { "create" },
+            format!("database {}", database),
+        );
+        database_change.statements = database_statements(
+            &db_config.username,
+            database,
+            db_config.database_options.get(database),
+            database_exists,
+        );
+        changes.push(database_change);
+    }
+
+    if db_config.create_secret() {
+        changes.push(change(
+            if secret_exists { "update" } else { "create" },
+            secret_resource,
+        ));
+        if db_config.config_map {
+            changes.push(change(
+                "apply",
+                format!(
+                    "ConfigMap {}/{}",
+                    db_config.namespace,
+                    config_map_name(&db_config.username)
+                ),
+            ));
+        }
+    }
+
+    Ok(changes)
+}
+
+fn database_statements(
+    username: &str,
+    database: &str,
+    options: Option<&DatabaseOptions>,
+    database_exists: bool,
+) -> Vec<String> {
+    let default_options = DatabaseOptions::default();
+    let options = options.unwrap_or(&default_options);
+    let mut statements = Vec::new();
+    let privilege_statements = database_privilege_statements(username, database, Some(options));
+    let schema_statements = schema_privilege_statements(username, options);
+    let create_statement = create_database_statement(database, Some(options));
+    let (username, database) = (quote_identifier(username), quote_identifier(database));
+
+    if !database_exists {
+        statements.push(create_statement);
+    }
+    statements.extend(privilege_statements);
+    if options.owner {
+        statements.push(format!("ALTER DATABASE {} OWNER TO {}", database, username));
+        statements.push(format!("ALTER SCHEMA public OWNER TO {}", username));
+    }
+    if let Some(connection_limit) = options.connection_limit {
+        statements.push(format!(
+            "ALTER DATABASE {} WITH CONNECTION LIMIT {}",
+            database, connection_limit
+        ));
+    }
+    if let Some(allow_connections) = options.allow_connections {
+        statements.push(format!(
+            "ALTER DATABASE {} WITH ALLOW_CONNECTIONS {}",
+            database, allow_connections
+        ));
+    }
+    for extension in options.extensions.iter() {
+        statements.push(format!(
+            "CREATE EXTENSION IF NOT EXISTS {}",
+            quote_identifier(extension)
+        ));
+    }
+    for schema in options.schemas.iter() {
+        statements.push(format!(
+            "CREATE SCHEMA IF NOT EXISTS {} AUTHORIZATION {}",
+            quote_identifier(schema),
+            username
+        ));
+    }
+    statements.extend(schema_statements);
+    statements
+}
+
+async fn secret_exists(
+    kube_client: &kube::Client,
+    db_config: &DatabaseConfig,
+    secret_name: &str,
+) -> anyhow::Result<bool> {
+    if db_config.csi.is_some() {
+        return csi::secret_provider_class_exists(kube_client, &db_config.namespace, secret_name)
+            .await;
+    }
+
+    let secrets: kube::api::Api<Secret> =
+        kube::api::Api::namespaced(kube_client.clone(), &db_config.namespace);
+    match retry(&format!("secret {}", secret_name), transient_kube, || {
+        secrets.get(secret_name)
+    })
+    .await
+    {
+        Ok(_) => Ok(true),
+        Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn count(changes: &[PlannedChange], action: &str) -> usize {
+    changes
+        .iter()
+        .filter(|change| change.action == action)
+        .count()
+}
+
+fn summary(changes: &[PlannedChange]) -> String {
+    format!(
+        "{} to create, {} to update, {} to apply, {} unchanged",
+        count(changes, "create"),
+        count(changes, "update"),
+        count(changes, "apply"),
+        count(changes, "unchanged")
+    )
+}
+
+fn print_text(changes: &[PlannedChange]) {
+    for change in changes.iter() {
+        let symbol = match change.action {
+            "create" => "+",
+            "update" | "apply" => "~",
+            _ => " ",
+        };
+        println!(
+            "{} {} {} ({})",
+            symbol, change.action, change.resource, change.username
+        );
+        for statement in change.statements.iter() {
+            println!("    {}", statement);
+        }
+    }
+    println!("plan: {}", summary(changes));
+}
+
+fn print_json(changes: &[PlannedChange]) -> anyhow::Result<()> {
+    let plan = serde_json::json!({
+        "summary": {
+            "create": count(changes, "create"),
+            "update": count(changes, "update"),
+            "apply": count(changes, "apply"),
+            "unchanged": count(changes, "unchanged"),
+        },
+        "changes": changes,
+    });
+    println!("{}", serde_json::to_string_pretty(&plan)?);
+    Ok(())
+}
+
+fn print_markdown(changes: &[PlannedChange]) {
+    println!("### kube-postgres-bootstrap plan");
+    println!();
+    println!("**{}**", summary(changes));
+    println!();
+    println!("| Action | Resource | User |");
+    println!("| --- | --- | --- |");
+    for change in changes.iter() {
+        println!(
+            "| {} | `{}` | `{}` |",
+            change.action, change.resource, change.username
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use std::{collections::BTreeMap, env};
+
+use k8s_openapi::api::core::v1::{Namespace, Secret};
+use kube::error::ErrorResponse;
+use tracing::info;
+
+use crate::{
+    backup, env_required, quote_identifier, quote_literal, sanitize_identifier, secret_name,
+    setup_account_for_config, timing::TimedClient, ConfigError, DBClients, DBConnection,
+    DatabaseConfig, DatabaseOptions,
+};
+
+// preview roles are tagged with a comment so gc can find them again once the
+// namespace (and with it the secret) is gone
+const PREVIEW_COMMENT_PREFIX: &str = "kube-postgres-bootstrap preview namespace=";
+
+// a per-branch user and database, both named from PREVIEW_NAME_TEMPLATE with
+// {branch} replaced by the sanitized PREVIEW_BRANCH
+struct PreviewConfig {
+    name: String,
+    namespace: String,
+    template_database: Option<String>,
+}
+
+impl PreviewConfig {
+    fn from_env() -> Result<PreviewConfig, ConfigError> {
+        let branch = env_required("PREVIEW_BRANCH")?;
+        let template =
+            env::var("PREVIEW_NAME_TEMPLATE").unwrap_or_else(|_| "preview_{branch}".to_string());
+
+        Ok(PreviewConfig {
+            name: sanitize_identifier(&template.replace("{branch}", &branch)),
+            namespace: env_required("PREVIEW_NAMESPACE")?,
+            template_database: env::var("PREVIEW_TEMPLATE_DATABASE").ok(),
+        })
+    }
+}
+
+pub async fn run(
+    action: &str,
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+) -> anyhow::Result<()> {
+    match action {
+        "up" => {
+            let preview = PreviewConfig::from_env()?;
+            up(clients, kube_client, db_connection_details, &preview).await
+        }
+        "down" => {
+            let preview = PreviewConfig::from_env()?;
+            down(
+                clients,
+                kube_client,
+                db_connection_details,
+                &preview.name,
+                Some(&preview.namespace),
+            )
+            .await
+        }
+        "gc" => gc(clients, kube_client, db_connection_details).await,
+        action => anyhow::bail!("unknown preview action {}, expected up, down or gc", action),
+    }
+}
+
+async fn up(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    preview: &PreviewConfig,
+) -> anyhow::Result<()> {
+    info!(
+        "provisioning preview environment {} in namespace {}",
+        preview.name, preview.namespace
+    );
+    let db_config = DatabaseConfig {
+        username: preview.name.clone(),
+        databases: vec![preview.name.clone()],
+        namespace: preview.namespace.clone(),
+        database_options: BTreeMap::from([(
+            preview.name.clone(),
+            DatabaseOptions {
+                clone_from: preview.template_database.clone(),
+                ..Default::default()
+            },
+        )]),
+        ..Default::default()
+    };
+    setup_account_for_config(clients, kube_client, db_connection_details, db_config).await?;
+
+    clients
+        .admin
+        .timed_execute(
+            format!(
+                "COMMENT ON ROLE {} IS {}",
+                quote_identifier(&preview.name),
+                quote_literal(&format!("{}{}", PREVIEW_COMMENT_PREFIX, preview.namespace))
+            )
+            .as_str(),
+            &[],
+        )
+        .await?;
+
+    Ok(())
+}
+
+// removes the database, user and (if the namespace still exists) the secret
+async fn down(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    name: &str,
+    namespace: Option<&str>,
+) -> anyhow::Result<()> {
+    info!("tearing down preview environment {}", name);
+
+    let db_exists = clients
+        .admin
+        .timed_query("SELECT 1 FROM pg_database WHERE datname = $1;", &[&name])
+        .await?;
+    if !db_exists.is_empty() {
+        backup::backup_database(
+            clients.privileged_connection_details(db_connection_details),
+            name,
+        )
+        .await?;
+    }
+
+    // sessions from the preview deployment would block the drop
+    clients
+        .privileged()
+        .timed_execute(
+            "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname = $1 AND pid <> pg_backend_pid();",
+            &[&name],
+        )
+        .await?;
+    clients
+        .admin
+        .timed_execute(
+            format!("DROP DATABASE IF EXISTS {}", quote_identifier(name)).as_str(),
+            &[],
+        )
+        .await?;
+    clients
+        .admin
+        .timed_execute(
+            format!("DROP ROLE IF EXISTS {}", quote_identifier(name)).as_str(),
+            &[],
+        )
+        .await?;
+
+    if let Some(namespace) = namespace {
+        let secrets: kube::api::Api<Secret> =
+            kube::api::Api::namespaced(kube_client.clone(), namespace);
+        match secrets
+            .delete(
+                &secret_name(&DatabaseConfig {
+                    username: name.to_string(),
+                    namespace: namespace.to_string(),
+                    ..Default::default()
+                }),
+                &kube::api::DeleteParams::default(),
+            )
+            .await
+        {
+            Ok(_) | Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    info!("successfully tore down preview environment {}", name);
+    Ok(())
+}
+
+// tears down every preview environment whose namespace no longer exists
+async fn gc(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+) -> anyhow::Result<()> {
+    let namespaces: kube::api::Api<Namespace> = kube::api::Api::all(kube_client.clone());
+    let previews = clients
+        .admin
+        .timed_query(
+            "SELECT rolname, shobj_description(oid, 'pg_authid') FROM pg_roles WHERE shobj_description(oid, 'pg_authid') LIKE $1;",
+            &[&format!("{}%", PREVIEW_COMMENT_PREFIX)],
+        )
+        .await?;
+
+    for row in previews.iter() {
+        let name: String = row.get(0);
+        let comment: String = row.get(1);
+        let namespace = comment.trim_start_matches(PREVIEW_COMMENT_PREFIX);
+
+        match namespaces.get(namespace).await {
+            Ok(_) => info!(
+                "preview environment {} still has namespace {}, keeping",
+                name, namespace
+            ),
+            Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => {
+                down(clients, kube_client, db_connection_details, &name, None).await?
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::{Api, DeleteParams, Patch, PatchParams},
+    error::ErrorResponse,
+};
+use tracing::info;
+
+use crate::{
+    build_secret, cockroach::Cockroach, create_pending_secret, events, finish_pending_secret,
+    inventory, is_pending, manifests, metrics, mysql::MySql, password, read_admin_secret,
+    replicate_secret, retry, secret_name, secret_owner_references, secret_value,
+    write_secret_stores, DBConnection, DatabaseConfig, Error,
+};
+
+// the database server an entry is provisioned on. postgres entries go through
+// setup_account_for_config, which covers far more than these steps, the other
+// engines are provisioned through Provisioner. cockroachdb speaks the postgres
+// protocol but not all of its catalogs and ddl, so it has its own statements.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    #[default]
+    Postgres,
+    #[serde(alias = "mariadb")]
+    Mysql,
+    #[serde(alias = "cockroach")]
+    Cockroachdb,
+}
+
+impl Engine {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Engine::Postgres => "postgres",
+            Engine::Mysql => "mysql",
+            Engine::Cockroachdb => "cockroachdb",
+        }
+    }
+
+    // the engine recorded in a secret's annotation
+    pub fn parse(name: &str) -> Option<Engine> {
+        [Engine::Postgres, Engine::Mysql, Engine::Cockroachdb]
+            .into_iter()
+            .find(|engine| engine.as_str() == name)
+    }
+}
+
+// the sql of an engine, every step has to be idempotent
+pub(crate) trait Provisioner {
+    async fn user_exists(&mut self, username: &str) -> Result<bool, Error>;
+    async fn create_user(&mut self, username: &str, password: &str) -> Result<(), Error>;
+    async fn set_password(&mut self, username: &str, password: &str) -> Result<(), Error>;
+    async fn drop_user(&mut self, username: &str) -> Result<(), Error>;
+    // true when the database was created
+    async fn ensure_database(&mut self, database: &str) -> Result<bool, Error>;
+    async fn drop_database(&mut self, database: &str) -> Result<(), Error>;
+    // privileges default to everything on the database
+    async fn grant(
+        &mut self,
+        username: &str,
+        database: &str,
+        privileges: Option<&[String]>,
+    ) -> Result<(), Error>;
+    // the keys of the entry's secret
+    fn secret_values(&self, db_config: &DatabaseConfig, password: &str)
+        -> BTreeMap<String, String>;
+    async fn disconnect(self);
+}
+
+// the admin credentials for an entry's server, from its adminSecret or
+// DB_USERNAME and DB_PASSWORD
+pub(crate) async fn admin_credentials(
+    kube_client: &kube::Client,
+    default: &DBConnection,
+    db_config: &DatabaseConfig,
+) -> Result<(String, String), Error> {
+    match &db_config.admin_secret {
+        Some(admin_secret) => match admin_secret.split_once('/') {
+            Some((namespace, name)) => read_admin_secret(kube_client, namespace, name).await,
+            None => read_admin_secret(kube_client, &db_config.namespace, admin_secret).await,
+        },
+        None => Ok((default.username.clone(), default.password.clone())),
+    }
+}
+
+// reconciles an entry of an engine other than postgres in the given mode,
+// repairing is the same as applying
+pub(crate) async fn run(
+    kube_client: &kube::Client,
+    default: &DBConnection,
+    mode: &str,
+    db_config: &DatabaseConfig,
+) -> Result<(), Error> {
+    match db_config.engine {
+        Engine::Mysql => {
+            let server = MySql::connect(kube_client, default, db_config).await?;
+            run_mode(server, kube_client, mode, db_config).await
+        }
+        Engine::Cockroachdb => {
+            let server = Cockroach::connect(kube_client, default, db_config).await?;
+            run_mode(server, kube_client, mode, db_config).await
+        }
+        Engine::Postgres => unreachable!("postgres entries are set up by setup_account_for_config"),
+    }
+}
+
+async fn run_mode(
+    mut server: impl Provisioner,
+    kube_client: &kube::Client,
+    mode: &str,
+    db_config: &DatabaseConfig,
+) -> Result<(), Error> {
+    let result = match mode {
+        "rotate" => rotate(&mut server, kube_client, db_config).await,
+        _ => apply(&mut server, kube_client, db_config).await,
+    };
+    server.disconnect().await;
+    result
+}
+
+// drops a pruned entry's user, and its databases with --prune-databases
+pub(crate) async fn drop_entry(
+    kube_client: &kube::Client,
+    default: &DBConnection,
+    db_config: &DatabaseConfig,
+    drop_databases: bool,
+) -> Result<(), Error> {
+    match db_config.engine {
+        Engine::Mysql => {
+            let mut server = MySql::connect(kube_client, default, db_config).await?;
+            let result = delete(&mut server, kube_client, db_config, drop_databases).await;
+            server.disconnect().await;
+            result?
+        }
+        Engine::Cockroachdb => {
+            let mut server = Cockroach::connect(kube_client, default, db_config).await?;
+            let result = delete(&mut server, kube_client, db_config, drop_databases).await;
+            server.disconnect().await;
+            result?
+        }
+        Engine::Postgres => unreachable!("postgres entries are dropped by delete::delete"),
+    }
+    inventory::remove(kube_client, &db_config.namespace, &db_config.username).await
+}
+
+// creates the entry's user, databases and secret. like for postgres an existing
+// secret keeps its password and gets the keys of new databases merged in.
+pub(crate) async fn apply(
+    provisioner: &mut impl Provisioner,
+    kube_client: &kube::Client,
+    db_config: &DatabaseConfig,
+) -> Result<(), Error> {
+    let username = db_config.username.as_str();
+    let secrets: Api<Secret> = Api::namespaced(kube_client.clone(), &db_config.namespace);
+    let secret_name = secret_name(db_config);
+    let secret_resource = format!("secret {}/{}", db_config.namespace, secret_name);
+    let existing_secret = match retry::retry(&secret_resource, retry::transient_kube, || {
+        secrets.get(&secret_name)
+    })
+    .await
+    {
+        Ok(secret) => Some(secret),
+        Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => None,
+        Err(e) => return Err(Error::kube(secret_resource)(e)),
+    };
+
+    let password = match &existing_secret {
+        Some(secret) => {
+            info!(
+                "Secret {} already exists, keeping its password",
+                secret_name
+            );
+            secret_value(secret, &db_config.password_key()).ok_or_else(|| {
+                Error::invalid(username, format!("{} has no password key", secret_resource))
+            })?
+        }
+        None => password::generate(db_config.password_policy.as_ref()),
+    };
+    // see PENDING_ANNOTATION
+    let mut pending = existing_secret.as_ref().is_some_and(is_pending);
+    if existing_secret.is_none() && !manifests::enabled() {
+        create_pending_secret(&secrets, db_config, &secret_name, &password).await?;
+        pending = true;
+    }
+    if !provisioner.user_exists(username).await? {
+        info!("user does not exist, creating... {}", username);
+        provisioner.create_user(username, &password).await?;
+        metrics::user_created();
+        events::normal(
+            &db_config.namespace,
+            &secret_name,
+            "UserCreated",
+            format!("created {} user {}", db_config.engine.as_str(), username),
+        )
+        .await;
+    } else if existing_secret.is_none() || pending {
+        info!("user already exists, updating password... {}", username);
+        provisioner.set_password(username, &password).await?;
+    }
+
+    for db in db_config.databases.iter() {
+        if provisioner.ensure_database(db).await? {
+            info!("created database {}", db);
+        }
+        let privileges = db_config
+            .database_options
+            .get(db)
+            .and_then(|options| options.privileges.as_deref());
+        info!("ensuring user {} has access to database {}", username, db);
+        provisioner.grant(username, db, privileges).await?;
+    }
+
+    let secret_data = provisioner.secret_values(db_config, &password);
+    let owner_references = secret_owner_references(kube_client, db_config).await?;
+    let secret = build_secret(db_config, &secret_name, &secret_data, owner_references);
+    if manifests::enabled() {
+        manifests::write(&secret)
+            .await
+            .map_err(Error::output(username, "manifest"))?;
+    } else if existing_secret.is_some() && !pending {
+        let patch_params = PatchParams::default();
+        let patch = Patch::Merge(serde_json::json!({
+            "metadata": {
+                "labels": secret.metadata.labels,
+                "annotations": secret.metadata.annotations,
+            },
+            "stringData": secret_data,
+        }));
+        retry::retry(&secret_resource, retry::transient_kube, || {
+            secrets.patch(&secret_name, &patch_params, &patch)
+        })
+        .await
+        .map_err(Error::kube(secret_resource))?;
+        info!("successfully updated secret with db creds: {}", secret_name);
+        metrics::secret_updated();
+    } else {
+        finish_pending_secret(&secrets, &secret).await?;
+        info!("successfully created secret with db creds: {}", secret_name);
+        metrics::secret_created();
+        events::normal(
+            &db_config.namespace,
+            &secret_name,
+            "SecretCreated",
+            format!(
+                "created secret {} with the credentials of user {}",
+                secret_name, username
+            ),
+        )
+        .await;
+    }
+    replicate_secret(kube_client, db_config, &secret_data).await?;
+    write_secret_stores(db_config, &secret_data).await
+}
+
+// sets a new password and writes it to the secret, recreating a missing user
+pub(crate) async fn rotate(
+    provisioner: &mut impl Provisioner,
+    kube_client: &kube::Client,
+    db_config: &DatabaseConfig,
+) -> Result<(), Error> {
+    let username = db_config.username.as_str();
+    let password = password::generate(db_config.password_policy.as_ref());
+    if provisioner.user_exists(username).await? {
+        info!("rotating password of user {}", username);
+        provisioner.set_password(username, &password).await?;
+    } else {
+        info!("user does not exist, creating... {}", username);
+        provisioner.create_user(username, &password).await?;
+    }
+
+    let secrets: Api<Secret> = Api::namespaced(kube_client.clone(), &db_config.namespace);
+    let secret_name = secret_name(db_config);
+    let secret_resource = format!("secret {}/{}", db_config.namespace, secret_name);
+    let secret_data = provisioner.secret_values(db_config, &password);
+    if manifests::enabled() {
+        let owner_references = secret_owner_references(kube_client, db_config).await?;
+        let secret = build_secret(db_config, &secret_name, &secret_data, owner_references);
+        manifests::write(&secret)
+            .await
+            .map_err(Error::output(username, "manifest"))?;
+        replicate_secret(kube_client, db_config, &secret_data).await?;
+        return write_secret_stores(db_config, &secret_data).await;
+    }
+    let patch_params = PatchParams::default();
+    let patch = Patch::Merge(serde_json::json!({ "stringData": secret_data }));
+    retry::retry(&secret_resource, retry::transient_kube, || {
+        secrets.patch(&secret_name, &patch_params, &patch)
+    })
+    .await
+    .map_err(Error::kube(secret_resource))?;
+    info!("successfully rotated credentials in secret {}", secret_name);
+    metrics::secret_updated();
+    events::normal(
+        &db_config.namespace,
+        &secret_name,
+        "RotationPerformed",
+        format!(
+            "rotated the password of user {} in secret {}",
+            username, secret_name
+        ),
+    )
+    .await;
+    replicate_secret(kube_client, db_config, &secret_data).await?;
+    write_secret_stores(db_config, &secret_data).await
+}
+
+// drops the user, its databases when asked to, and deletes its secret
+pub(crate) async fn delete(
+    provisioner: &mut impl Provisioner,
+    kube_client: &kube::Client,
+    db_config: &DatabaseConfig,
+    drop_databases: bool,
+) -> Result<(), Error> {
+    let username = db_config.username.as_str();
+    if drop_databases {
+        for db in db_config.databases.iter() {
+            info!("dropping database {}", db);
+            provisioner.drop_database(db).await?;
+        }
+    }
+    info!("dropping user {}", username);
+    provisioner.drop_user(username).await?;
+
+    let secret_name = secret_name(db_config);
+    for namespace in std::iter::once(&db_config.namespace).chain(&db_config.extra_namespaces) {
+        let secrets: Api<Secret> = Api::namespaced(kube_client.clone(), namespace);
+        match secrets.delete(&secret_name, &DeleteParams::default()).await {
+            Ok(_) | Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => {}
+            Err(e) => return Err(Error::kube(format!("secret {}/{}", namespace, secret_name))(e)),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,408 @@
+use std::{
+    collections::BTreeSet,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use k8s_openapi::api::{
+    apps::v1::{DaemonSet, Deployment, StatefulSet},
+    batch::v1::CronJob,
+    core::v1::{Pod, PodSpec, Secret},
+};
+use kube::api::{Api, DeleteParams, ListParams};
+use tracing::{info, warn};
+
+use crate::{
+    delete, env_flag,
+    provisioner::{self, Engine},
+    report::Report,
+    rotate, secret_entry, DBClients, DBConnection, DatabaseConfig, Error, ServerConnections,
+    MANAGED_BY, MANAGED_BY_LABEL,
+};
+
+static PRUNE: AtomicBool = AtomicBool::new(false);
+static PRUNE_DATABASES: AtomicBool = AtomicBool::new(false);
+
+// set from --prune and --prune-databases before anything runs
+pub fn configure(prune: bool, prune_databases: bool) {
+    PRUNE.store(prune, Ordering::Relaxed);
+    PRUNE_DATABASES.store(prune_databases, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    PRUNE.load(Ordering::Relaxed) || env_flag("PRUNE_SECRETS")
+}
+
+// a user is its name on the server it's on, entries that leave out host and port
+// are on DB_HOST
+pub(crate) fn user_key(
+    db_config: &DatabaseConfig,
+    default: &DBConnection,
+) -> (String, String, String) {
+    match db_config.server(default) {
+        Some(server) => (db_config.username.clone(), server.host, server.port),
+        None => (
+            db_config.username.clone(),
+            default.host.clone(),
+            default.port.clone(),
+        ),
+    }
+}
+
+// what pruning an unexpected secret removes
+#[derive(Debug)]
+enum Orphan {
+    Secret,
+    User(Box<DatabaseConfig>),
+}
+
+// an entry that moved to another namespace or changed its secret name leaves its
+// old secret behind while the user is still in use, only the secret goes then
+fn orphan(
+    secret: &Secret,
+    expected_secrets: &BTreeSet<(String, String)>,
+    expected_users: &BTreeSet<(String, String, String)>,
+    db_connection_details: &DBConnection,
+    drop_users: bool,
+) -> Option<Orphan> {
+    let name = secret.metadata.name.clone().unwrap_or_default();
+    let namespace = secret.metadata.namespace.clone().unwrap_or_default();
+    if expected_secrets.contains(&(namespace, name)) {
+        return None;
+    }
+    match secret_entry(secret) {
+        // secrets that aren't an entry only have the secret deleted
+        Some(db_config)
+            if drop_users
+                && !expected_users.contains(&user_key(&db_config, db_connection_details)) =>
+        {
+            Some(Orphan::User(Box::new(db_config)))
+        }
+        _ => Some(Orphan::Secret),
+    }
+}
+
+// deletes secrets created by the bootstrap whose config entry has been removed.
+// secrets that are still referenced by a workload in their namespace are kept.
+// with --prune the user recorded on the secret is dropped with it, unless an
+// entry on the same server still has that username, and its databases with
+// --prune-databases. failures are recorded in the report.
+pub(crate) async fn prune_secrets(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    servers: &ServerConnections,
+    expected_secrets: &BTreeSet<(String, String)>,
+    expected_users: &BTreeSet<(String, String, String)>,
+    report: &mut Report,
+) -> Result<(), Error> {
+    let secrets: Api<Secret> = Api::all(kube_client.clone());
+    let managed = secrets
+        .list(&ListParams::default().labels(&format!("{}={}", MANAGED_BY_LABEL, MANAGED_BY)))
+        .await
+        .map_err(Error::kube("managed secrets".to_string()))?;
+
+    for secret in managed.items.iter() {
+        let name = secret.metadata.name.clone().unwrap_or_default();
+        let namespace = secret.metadata.namespace.clone().unwrap_or_default();
+        let orphan = match orphan(
+            secret,
+            expected_secrets,
+            expected_users,
+            db_connection_details,
+            PRUNE.load(Ordering::Relaxed),
+        ) {
+            Some(orphan) => orphan,
+            None => continue,
+        };
+
+        let references = secret_references(kube_client, &namespace, &name).await?;
+        if !references.is_empty() {
+            warn!(
+                "not pruning secret {}/{} as it is still referenced by {}",
+                namespace,
+                name,
+                references.join(", ")
+            );
+            continue;
+        }
+
+        let result = match orphan {
+            Orphan::User(db_config) => {
+                info!(
+                    "pruning user {} and secret {}/{}",
+                    db_config.username, namespace, name
+                );
+                drop_user(
+                    clients,
+                    kube_client,
+                    db_connection_details,
+                    servers,
+                    &db_config,
+                )
+                .await
+            }
+            Orphan::Secret => {
+                info!("pruning secret {}/{}", namespace, name);
+                Api::<Secret>::namespaced(kube_client.clone(), &namespace)
+                    .delete(&name, &DeleteParams::default())
+                    .await
+                    .map(|_| ())
+                    .map_err(Error::kube(format!("secret {}/{}", namespace, name)))
+            }
+        };
+        report.record(format!("pruning {}/{}", namespace, name), Ok(result));
+    }
+
+    // entries rotated with ROTATION_GRACE_PERIOD_SECONDS leave a role behind
+    let result = rotate::drop_expired_previous_roles(&clients.admin, None)
+        .await
+        .map_err(Error::db("admin", "dropping expired previous roles"));
+    report.record("dropping expired previous roles".to_string(), Ok(result));
+
+    Ok(())
+}
+
+// drops the user on the server it was created on, which also deletes its secret
+async fn drop_user(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    servers: &ServerConnections,
+    db_config: &DatabaseConfig,
+) -> Result<(), Error> {
+    if db_config.engine != Engine::Postgres {
+        return provisioner::drop_entry(
+            kube_client,
+            db_connection_details,
+            db_config,
+            PRUNE_DATABASES.load(Ordering::Relaxed),
+        )
+        .await;
+    }
+    let server = match db_config.server(db_connection_details) {
+        None => None,
+        Some(server) => Some(
+            servers
+                .get(kube_client, &server, db_connection_details)
+                .await?,
+        ),
+    };
+    let (clients, db_connection_details) = match &server {
+        None => (clients, db_connection_details),
+        Some(connection) => (&connection.0, &connection.1),
+    };
+    delete::delete(
+        clients,
+        kube_client,
+        db_connection_details,
+        db_config,
+        PRUNE_DATABASES.load(Ordering::Relaxed),
+        false,
+    )
+    .await
+    .map_err(Error::Other)
+}
+
+// lists the workloads in the namespace that mount or read env from the secret
+async fn secret_references(
+    kube_client: &kube::Client,
+    namespace: &str,
+    secret: &str,
+) -> anyhow::Result<Vec<String>> {
+    let mut references = Vec::new();
+    let lp = ListParams::default();
+
+    for pod in Api::<Pod>::namespaced(kube_client.clone(), namespace)
+        .list(&lp)
+        .await?
+    {
+        if pod
+            .spec
+            .as_ref()
+            .is_some_and(|spec| pod_spec_references_secret(spec, secret))
+        {
+            references.push(format!("pod/{}", pod.metadata.name.unwrap_or_default()));
+        }
+    }
+    for deployment in Api::<Deployment>::namespaced(kube_client.clone(), namespace)
+        .list(&lp)
+        .await?
+    {
+        let spec = deployment.spec.and_then(|spec| spec.template.spec);
+        if spec.is_some_and(|spec| pod_spec_references_secret(&spec, secret)) {
+            references.push(format!(
+                "deployment/{}",
+                deployment.metadata.name.unwrap_or_default()
+            ));
+        }
+    }
+    for stateful_set in Api::<StatefulSet>::namespaced(kube_client.clone(), namespace)
+        .list(&lp)
+        .await?
+    {
+        let spec = stateful_set.spec.and_then(|spec| spec.template.spec);
+        if spec.is_some_and(|spec| pod_spec_references_secret(&spec, secret)) {
+            references.push(format!(
+                "statefulset/{}",
+                stateful_set.metadata.name.unwrap_or_default()
+            ));
+        }
+    }
+    for daemon_set in Api::<DaemonSet>::namespaced(kube_client.clone(), namespace)
+        .list(&lp)
+        .await?
+    {
+        let spec = daemon_set.spec.and_then(|spec| spec.template.spec);
+        if spec.is_some_and(|spec| pod_spec_references_secret(&spec, secret)) {
+            references.push(format!(
+                "daemonset/{}",
+                daemon_set.metadata.name.unwrap_or_default()
+            ));
+        }
+    }
+    for cron_job in Api::<CronJob>::namespaced(kube_client.clone(), namespace)
+        .list(&lp)
+        .await?
+    {
+        let spec = cron_job
+            .spec
+            .and_then(|spec| spec.job_template.spec)
+            .and_then(|spec| spec.template.spec);
+        if spec.is_some_and(|spec| pod_spec_references_secret(&spec, secret)) {
+            references.push(format!(
+                "cronjob/{}",
+                cron_job.metadata.name.unwrap_or_default()
+            ));
+        }
+    }
+
+    Ok(references)
+}
+
+fn pod_spec_references_secret(spec: &PodSpec, secret: &str) -> bool {
+    let secret = Some(secret);
+
+    let in_containers = spec
+        .containers
+        .iter()
+        .chain(spec.init_containers.iter().flatten())
+        .any(|container| {
+            let in_env = container.env.iter().flatten().any(|env| {
+                env.value_from
+                    .as_ref()
+                    .and_then(|source| source.secret_key_ref.as_ref())
+                    .is_some_and(|key_ref| key_ref.name.as_deref() == secret)
+            });
+            let in_env_from = container.env_from.iter().flatten().any(|env_from| {
+                env_from
+                    .secret_ref
+                    .as_ref()
+                    .is_some_and(|secret_ref| secret_ref.name.as_deref() == secret)
+            });
+            in_env || in_env_from
+        });
+
+    let in_volumes = spec.volumes.iter().flatten().any(|volume| {
+        let in_secret_volume = volume
+            .secret
+            .as_ref()
+            .is_some_and(|source| source.secret_name.as_deref() == secret);
+        let in_projected_volume = volume
+            .projected
+            .as_ref()
+            .and_then(|projected| projected.sources.as_ref())
+            .is_some_and(|sources| {
+                sources.iter().any(|projection| {
+                    projection
+                        .secret
+                        .as_ref()
+                        .is_some_and(|projection| projection.name.as_deref() == secret)
+                })
+            });
+        in_secret_volume || in_projected_volume
+    });
+
+    in_containers || in_volumes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_server() -> DBConnection {
+        DBConnection {
+            host: "postgres".to_string(),
+            port: "5432".to_string(),
+            username: "postgres".to_string(),
+            password: String::new(),
+        }
+    }
+
+    fn secret(namespace: &str, name: &str, username: &str) -> Secret {
+        serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "name": name,
+                "namespace": namespace,
+                "annotations": { crate::USERNAME_ANNOTATION: username },
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn moved_entries_keep_their_user() {
+        let moved = secret("old-team", "app-db-credentials", "app");
+        let expected_secrets =
+            BTreeSet::from([("new-team".to_string(), "app-db-credentials".to_string())]);
+        let expected_users = BTreeSet::from([(
+            "app".to_string(),
+            "postgres".to_string(),
+            "5432".to_string(),
+        )]);
+        let orphan = orphan(
+            &moved,
+            &expected_secrets,
+            &expected_users,
+            &default_server(),
+            true,
+        );
+        assert!(matches!(orphan, Some(Orphan::Secret)));
+    }
+
+    #[test]
+    fn removed_entries_drop_their_user() {
+        let removed = secret("team", "app-db-credentials", "app");
+        let dropped = orphan(
+            &removed,
+            &BTreeSet::new(),
+            &BTreeSet::new(),
+            &default_server(),
+            true,
+        );
+        assert!(matches!(dropped, Some(Orphan::User(db_config)) if db_config.username == "app"));
+        // without --prune only the secret goes
+        let deleted = orphan(
+            &removed,
+            &BTreeSet::new(),
+            &BTreeSet::new(),
+            &default_server(),
+            false,
+        );
+        assert!(matches!(deleted, Some(Orphan::Secret)));
+    }
+
+    #[test]
+    fn expected_secrets_are_kept() {
+        let kept = secret("team", "app-db-credentials", "app");
+        let expected_secrets =
+            BTreeSet::from([("team".to_string(), "app-db-credentials".to_string())]);
+        let orphan = orphan(
+            &kept,
+            &expected_secrets,
+            &BTreeSet::new(),
+            &default_server(),
+            true,
+        );
+        assert!(orphan.is_none());
+    }
+}
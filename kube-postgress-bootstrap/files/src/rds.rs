@@ -0,0 +1,57 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use aws_credential_types::provider::ProvideCredentials;
+use aws_sigv4::{
+    http_request::{sign, SignableBody, SignableRequest, SignatureLocation, SigningSettings},
+    sign::v4,
+};
+
+use crate::percent_encode;
+
+// how long rds accepts a token for new connections, established ones stay open
+const TOKEN_LIFETIME: Duration = Duration::from_secs(900);
+
+// an rds iam auth token for logging in as username, which is a presigned
+// rds-db connect url without its scheme. the credentials and region come from
+// the default aws chain, e.g. the pod's service account through irsa.
+pub async fn auth_token(host: &str, port: &str, username: &str) -> anyhow::Result<String> {
+    let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    let region = aws_config
+        .region()
+        .context("AWS_REGION must be set for rds iam auth")?
+        .to_string();
+    let credentials = aws_config
+        .credentials_provider()
+        .context("no aws credentials for rds iam auth")?
+        .provide_credentials()
+        .await
+        .context("failed to load aws credentials for rds iam auth")?;
+    let identity = credentials.into();
+
+    let mut settings = SigningSettings::default();
+    settings.signature_location = SignatureLocation::QueryParams;
+    settings.expires_in = Some(TOKEN_LIFETIME);
+    let params = v4::SigningParams::builder()
+        .identity(&identity)
+        .region(&region)
+        .name("rds-db")
+        .time(SystemTime::now())
+        .settings(settings)
+        .build()?
+        .into();
+
+    let url = format!(
+        "https://{}:{}/?Action=connect&DBUser={}",
+        host,
+        port,
+        percent_encode(username)
+    );
+    let request = SignableRequest::new("GET", &url, std::iter::empty(), SignableBody::Bytes(&[]))?;
+    let (instructions, _) = sign(request, &params)?.into_parts();
+    let mut token = url.trim_start_matches("https://").to_string();
+    for (name, value) in instructions.params() {
+        token.push_str(&format!("&{}={}", name, percent_encode(value)));
+    }
+    Ok(token)
+}
@@ -0,0 +1,118 @@
+use std::time::{Duration, Instant};
+
+use k8s_openapi::api::core::v1::ConfigMap;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::Notify,
+};
+use tracing::{error, info};
+
+use crate::{
+    cli::{ConfigFormat, NamespaceOverrides},
+    env_number, health, heartbeat, read_config, reconcile, shutdown, source, DBClients,
+    DBConnection,
+};
+
+// how often the config is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+static NOTIFY: Notify = Notify::const_new();
+
+// a SIGHUP makes the long running modes reconcile right away instead of at the
+// next interval. a signal during a reconcile is kept for the one after it.
+pub fn listen() {
+    let mut hangup = signal(SignalKind::hangup()).expect("failed to listen for SIGHUP");
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("received SIGHUP, reloading");
+            NOTIFY.notify_one();
+        }
+    });
+}
+
+pub async fn wait() {
+    NOTIFY.notified().await
+}
+
+// bootstrap --watch: reconciles from the config, then again whenever the local
+// file or configmap:// changes, on SIGHUP and every WATCH_RESYNC_SECONDS
+// (defaults to 300) so drift is repaired. remote configs (s3://, https://, oci://)
+// are only read again on SIGHUP and resync. a failed reconcile is retried at the
+// next one instead of stopping the watch.
+pub async fn watch(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    config: &str,
+    format: Option<ConfigFormat>,
+    overrides: &NamespaceOverrides,
+    mode: &str,
+) -> anyhow::Result<()> {
+    let resync = Duration::from_secs(env_number("WATCH_RESYNC_SECONDS")?.unwrap_or(300));
+
+    loop {
+        // taken before reading, a change while reconciling triggers another one
+        let version = config_version(kube_client, config).await;
+        let result = match read_config(kube_client, config, format, overrides).await {
+            Ok(db_configs) => {
+                reconcile(
+                    clients,
+                    kube_client,
+                    db_connection_details,
+                    mode,
+                    db_configs,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        match &result {
+            Ok(()) => heartbeat::success().await,
+            Err(e) => error!("failed to reconcile from {}: {:#}", config, e),
+        }
+        health::cycle_finished(result.is_ok());
+
+        let resync_at = Instant::now() + resync;
+        loop {
+            if shutdown::requested() {
+                return Ok(());
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                _ = wait() => break,
+                _ = shutdown::wait() => return Ok(()),
+            }
+            if Instant::now() >= resync_at {
+                break;
+            }
+            if config_version(kube_client, config).await != version {
+                info!("{} changed, reconciling", config);
+                break;
+            }
+        }
+    }
+}
+
+// the modification time of a local config (of every file in a config directory)
+// or the resource version of a configmap:// one, None for remote configs
+async fn config_version(kube_client: &kube::Client, config: &str) -> Option<String> {
+    if config.starts_with(source::CONFIG_MAP_PREFIX) {
+        let (namespace, name) = source::split_config_map_location(config).ok()?;
+        let config_maps: kube::api::Api<ConfigMap> =
+            kube::api::Api::namespaced(kube_client.clone(), namespace);
+        return config_maps.get(name).await.ok()?.metadata.resource_version;
+    }
+    if config.contains("://") {
+        return None;
+    }
+    let files = source::config_files(config)
+        .ok()?
+        .unwrap_or_else(|| vec![config.to_string()]);
+    let mut version = String::new();
+    for file in files.iter() {
+        // follows the symlinks of mounted configmaps, which are swapped on updates
+        let modified = std::fs::metadata(file).ok()?.modified().ok()?;
+        version.push_str(&format!("{}={:?};", file, modified));
+    }
+    Some(version)
+}
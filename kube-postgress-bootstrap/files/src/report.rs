@@ -0,0 +1,134 @@
+use std::{any::Any, env};
+
+use anyhow::Context;
+use tracing::{debug, error, info, warn};
+
+use crate::Error;
+
+// outcome of every entry of a run, a failing entry doesn't stop the ones after it
+#[derive(Default)]
+pub(crate) struct Report {
+    results: Vec<EntryResult>,
+}
+
+#[derive(serde::Serialize)]
+struct EntryResult {
+    entry: String,
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    // why a skipped entry wasn't looked at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+impl Report {
+    // entries return a typed error, a panic is only caught as a safety net so one
+    // bug doesn't take down the other entries
+    pub fn record(&mut self, entry: String, result: std::thread::Result<Result<(), Error>>) {
+        let error = match result {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e.to_string()),
+            Err(panic) => Some(format!("panicked: {}", panic_message(&*panic))),
+        };
+        if let Some(error) = &error {
+            error!("{} failed: {}", entry, error);
+        }
+        self.results.push(EntryResult {
+            entry,
+            status: if error.is_some() { "failed" } else { "ok" },
+            error,
+            reason: None,
+        });
+    }
+
+    // entries that were neither run nor failed, e.g. the ones verify can't check
+    pub fn skip(&mut self, entry: String, reason: String) {
+        warn!("skipping {}: {}", entry, reason);
+        self.results.push(EntryResult {
+            entry,
+            status: "skipped",
+            error: None,
+            reason: Some(reason),
+        });
+    }
+
+    // entries left out by --only or --namespace-selector, kept apart from the
+    // skipped ones since nothing went wrong with them
+    pub fn filter(&mut self, entry: String, reason: String) {
+        debug!("filtered out {}: {}", entry, reason);
+        self.results.push(EntryResult {
+            entry,
+            status: "filtered",
+            error: None,
+            reason: Some(reason),
+        });
+    }
+
+    // the failed entries with their errors, for runs that report them elsewhere
+    pub fn errors(&self) -> Vec<String> {
+        self.results
+            .iter()
+            .filter_map(|result| {
+                let error = result.error.as_ref()?;
+                Some(format!("{}: {}", result.entry, error))
+            })
+            .collect()
+    }
+
+    // logs the summary and writes it as json to SUMMARY_FILE when set, the run
+    // fails if any entry did
+    pub fn finish(self) -> Result<(), Error> {
+        let count = |status: &str| {
+            self.results
+                .iter()
+                .filter(|result| result.status == status)
+                .count()
+        };
+        let (succeeded, failed, skipped) = (count("ok"), count("failed"), count("skipped"));
+        let filtered = count("filtered");
+        let mut summary = format!("summary: {} succeeded, {} failed", succeeded, failed);
+        if skipped > 0 {
+            summary.push_str(&format!(", {} skipped", skipped));
+        }
+        if filtered > 0 {
+            summary.push_str(&format!(", {} filtered out", filtered));
+        }
+        info!("{}", summary);
+        for result in self.results.iter() {
+            match (&result.error, &result.reason) {
+                (Some(error), _) => info!("  failed: {}: {}", result.entry, error),
+                (None, Some(reason)) => info!("  {}: {}: {}", result.status, result.entry, reason),
+                (None, None) => info!("  ok: {}", result.entry),
+            }
+        }
+
+        if let Ok(path) = env::var("SUMMARY_FILE") {
+            let summary = serde_json::json!({
+                "succeeded": succeeded,
+                "failed": failed,
+                "skipped": skipped,
+                "filtered": filtered,
+                "entries": self.results,
+            });
+            std::fs::write(&path, serde_json::to_string_pretty(&summary).unwrap())
+                .with_context(|| format!("failed to write summary to {}", path))?;
+        }
+
+        match failed {
+            0 => Ok(()),
+            failed => Err(Error::Failed {
+                failed,
+                total: self.results.len(),
+            }),
+        }
+    }
+}
+
+pub fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("panicked")
+}
@@ -0,0 +1,116 @@
+use std::{
+    fmt::Display,
+    future::Future,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+use tokio_postgres::error::SqlState;
+use tracing::warn;
+
+use crate::SqlError;
+
+const INITIAL_DELAY: Duration = Duration::from_millis(200);
+const MAX_DELAY: Duration = Duration::from_secs(10);
+
+struct Settings {
+    max_attempts: u32,
+    deadline: Duration,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+// set from --retry-max-attempts and --retry-deadline-seconds before anything runs
+pub fn configure(max_attempts: u32, deadline_seconds: u64) {
+    let _ = SETTINGS.set(Settings {
+        max_attempts: max_attempts.max(1),
+        deadline: Duration::from_secs(deadline_seconds),
+    });
+}
+
+fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| Settings {
+        max_attempts: 5,
+        deadline: Duration::from_secs(60),
+    })
+}
+
+// runs the operation until it succeeds, fails with an error that isn't transient,
+// runs out of attempts or would retry past the deadline. delays double from
+// INITIAL_DELAY up to MAX_DELAY, with full jitter so parallel jobs spread out.
+pub async fn retry<T, E, F, Fut>(
+    operation: &str,
+    transient: fn(&E) -> bool,
+    mut attempt: F,
+) -> Result<T, E>
+where
+    E: Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let settings = settings();
+    let start = Instant::now();
+    let mut delay = INITIAL_DELAY;
+    let mut attempts = 1;
+    loop {
+        let error = match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        let jittered = delay.mul_f64(rand::thread_rng().gen_range(0.0..=1.0));
+        if !transient(&error)
+            || attempts >= settings.max_attempts
+            || start.elapsed() + jittered > settings.deadline
+        {
+            return Err(error);
+        }
+
+        warn!(
+            "{} failed (attempt {}/{}), retrying in {}ms: {}",
+            operation,
+            attempts,
+            settings.max_attempts,
+            jittered.as_millis(),
+            error
+        );
+        tokio::time::sleep(jittered).await;
+        delay = (delay * 2).min(MAX_DELAY);
+        attempts += 1;
+    }
+}
+
+// errors without a sql state are io errors or timeouts, the rest are the server
+// refusing connections for now (starting up, failing over or full)
+pub fn transient_connect(e: &tokio_postgres::Error) -> bool {
+    match e.code() {
+        None => true,
+        Some(code) => {
+            code.code().starts_with("08")
+                || *code == SqlState::CANNOT_CONNECT_NOW
+                || *code == SqlState::TOO_MANY_CONNECTIONS
+                || *code == SqlState::ADMIN_SHUTDOWN
+        }
+    }
+}
+
+// a closed connection doesn't come back, so only conflicts with concurrent
+// transactions are worth running the statement again for. inside a transaction
+// the retry fails with the transaction being aborted instead.
+pub fn transient_query(e: &SqlError) -> bool {
+    matches!(
+        e.code(),
+        Some(code) if *code == SqlState::T_R_SERIALIZATION_FAILURE
+            || *code == SqlState::T_R_DEADLOCK_DETECTED
+            || *code == SqlState::LOCK_NOT_AVAILABLE
+    )
+}
+
+// throttling, server side errors and failing to reach the api server at all
+pub fn transient_kube(e: &kube::Error) -> bool {
+    match e {
+        kube::Error::Api(response) => matches!(response.code, 429 | 500 | 502 | 503 | 504),
+        kube::Error::HyperError(_) | kube::Error::Service(_) => true,
+        _ => false,
+    }
+}
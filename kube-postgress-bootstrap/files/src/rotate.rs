@@ -0,0 +1,497 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::{Api, ListParams, Patch, PatchParams},
+    error::ErrorResponse,
+};
+use tracing::info;
+
+use crate::{
+    age_output, build_secret, env_number, events, manifests, metrics, password, quote_identifier,
+    quote_literal, replicate_secret,
+    retry::{retry, transient_kube},
+    secret_entry, secret_name, secret_owner_references, secret_string_data, secret_value,
+    secret_values, setup_account_for_config,
+    timing::TimedClient,
+    write_secret_stores, ConfigError, DBClients, DBConnection, DatabaseConfig, Error, SecretFormat,
+    SqlError, COPY_OF_ANNOTATION, MANAGED_BY, MANAGED_BY_LABEL, PENDING_PASSWORD_KEY,
+};
+
+// generates a new password for an entry and patches it into the existing secret,
+// keeping the old credential under password_previous and database_url_previous.N
+// for consumers that are mid restart
+pub async fn rotate_credentials(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    db_config: DatabaseConfig,
+) -> Result<(), Error> {
+    let username = db_config.username.as_str();
+    // without a secret of ours there is nowhere to put the new password, and
+    // unmanaged users get their password from somebody else
+    if !db_config.create_secret() || !db_config.manage_database() || db_config.csi.is_some() {
+        info!(
+            "skipping rotation of user {} as its credentials aren't stored in a secret by the bootstrap",
+            username
+        );
+        return Ok(());
+    }
+    if db_config.iam_auth {
+        info!(
+            "skipping rotation of user {} as it logs in with iam tokens",
+            username
+        );
+        return Ok(());
+    }
+    let grace_period = grace_period()?;
+    let secrets: Api<Secret> = Api::namespaced(kube_client.clone(), &db_config.namespace);
+    let secret_name = secret_name(&db_config);
+    let existing_secret = match retry(&format!("secret {}", secret_name), transient_kube, || {
+        secrets.get(&secret_name)
+    })
+    .await
+    {
+        Ok(secret) => secret,
+        Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => {
+            info!(
+                "secret {} does not exist, creating instead of rotating",
+                secret_name
+            );
+            return setup_account_for_config(
+                clients,
+                kube_client,
+                db_connection_details,
+                db_config,
+            )
+            .await;
+        }
+        Err(e) => {
+            return Err(Error::kube(format!(
+                "secret {}/{}",
+                db_config.namespace, secret_name
+            ))(e))
+        }
+    };
+
+    let previous_password = secret_value(&existing_secret, &db_config.password_key());
+    // checked before the password changes, postgres would truncate the name
+    let previous_username = format!("{}_previous", username);
+    if grace_period.is_some() && previous_username.len() > 63 {
+        return Err(Error::invalid(
+            username,
+            format!(
+                "{} is longer than 63 bytes, the previous password can't be kept for ROTATION_GRACE_PERIOD_SECONDS",
+                previous_username
+            ),
+        ));
+    }
+    drop_expired_previous_roles(&clients.admin, Some(&previous_username))
+        .await
+        .map_err(Error::db(username, "dropping the expired previous role"))?;
+    let secret_resource = format!("secret {}/{}", db_config.namespace, secret_name);
+    let patch_params = PatchParams::default();
+
+    // see PENDING_PASSWORD_KEY, manifests have no secret in the cluster to keep
+    // the password in
+    let password = match secret_value(&existing_secret, PENDING_PASSWORD_KEY) {
+        Some(password) => {
+            info!(
+                "finishing the pending rotation of user {} from secret {}",
+                username, secret_name
+            );
+            password
+        }
+        None => {
+            let password = password::generate(db_config.password_policy.as_ref());
+            if !manifests::enabled() {
+                let patch = Patch::Merge(serde_json::json!({
+                    "stringData": { PENDING_PASSWORD_KEY: &password },
+                }));
+                retry(&secret_resource, transient_kube, || {
+                    secrets.patch(&secret_name, &patch_params, &patch)
+                })
+                .await
+                .map_err(Error::kube(secret_resource.clone()))?;
+            }
+            password
+        }
+    };
+    info!("rotating password for user {}", db_config.username);
+    clients
+        .admin
+        .timed_execute(
+            format!(
+                "ALTER USER {} WITH PASSWORD {};",
+                quote_identifier(username),
+                quote_literal(&password)
+            )
+            .as_str(),
+            &[],
+        )
+        .await
+        .map_err(Error::db(username, "rotating password"))?;
+
+    // the connection strings embed the password, so they're rotated along with it
+    let mut string_data: BTreeMap<String, String> = match db_config.secret_format {
+        SecretFormat::Default => secret_values(db_connection_details, &db_config, &password)
+            .into_iter()
+            .filter(|(_, value)| value.contains(&password))
+            .collect(),
+        SecretFormat::ServiceBinding => BTreeMap::from([("password".to_string(), password)]),
+    };
+    // the pending password is dropped, and without a grace period the
+    // username_previous of an earlier rotation as there's no role for it
+    let mut removed_keys = vec![PENDING_PASSWORD_KEY.to_string()];
+    if let Some(previous_password) = previous_password {
+        let previous_login = match grace_period {
+            Some(grace_period) => {
+                keep_previous_password(clients, username, &previous_password, grace_period)
+                    .await
+                    .map_err(Error::db(username, "keeping the previous password"))?;
+                string_data.insert("username_previous".to_string(), previous_username.clone());
+                previous_username.clone()
+            }
+            None => {
+                removed_keys.push("username_previous".to_string());
+                username.to_string()
+            }
+        };
+        string_data.extend(previous_values(
+            db_connection_details,
+            &db_config,
+            &previous_login,
+            &previous_password,
+        ));
+    }
+
+    if manifests::enabled() {
+        let mut data = secret_string_data(&existing_secret);
+        for key in removed_keys.iter() {
+            data.remove(key);
+        }
+        data.extend(string_data);
+        let owner_references = secret_owner_references(kube_client, &db_config).await?;
+        manifests::write(&build_secret(
+            &db_config,
+            &secret_name,
+            &data,
+            owner_references,
+        ))
+        .await
+        .map_err(Error::output(username, "manifest"))?;
+        replicate_secret(kube_client, &db_config, &data).await?;
+        write_secret_stores(&db_config, &data).await?;
+        age_output::write_bundle(&db_config.namespace, username, &data)
+            .map_err(Error::output(username, "age output"))?;
+        info!("wrote rotated credentials of secret {}", secret_name);
+        return Ok(());
+    }
+
+    // a single patch so consumers never observe a half rotated secret, it also
+    // drops the pending password
+    let removed: serde_json::Map<String, serde_json::Value> = removed_keys
+        .into_iter()
+        .map(|key| (key, serde_json::Value::Null))
+        .collect();
+    let patch = Patch::Merge(serde_json::json!({
+        "data": removed,
+        "stringData": string_data,
+    }));
+    let rotated_secret = retry(&secret_resource, transient_kube, || {
+        secrets.patch(&secret_name, &patch_params, &patch)
+    })
+    .await
+    .map_err(Error::kube(secret_resource))?;
+
+    // the copies get the same keys right after, consumers of a copy can briefly
+    // see the old password
+    let data = secret_string_data(&rotated_secret);
+    replicate_secret(kube_client, &db_config, &data).await?;
+    write_secret_stores(&db_config, &data).await?;
+    age_output::write_bundle(&db_config.namespace, username, &data)
+        .map_err(Error::output(username, "age output"))?;
+
+    info!("successfully rotated credentials in secret {}", secret_name);
+    metrics::secret_updated();
+    events::normal(
+        &db_config.namespace,
+        &secret_name,
+        "RotationPerformed",
+        format!(
+            "rotated the password of user {} in secret {}",
+            username, secret_name
+        ),
+    )
+    .await;
+    Ok(())
+}
+
+// narrows a rotation down to a single compromised credential with
+// --user=<username> [--namespace=<namespace>], or to every credential on a server
+// with --all-in-cluster=<host>
+pub struct RotationTarget {
+    pub user: Option<String>,
+    pub namespace: Option<String>,
+    pub all_in_cluster: Option<String>,
+}
+
+impl RotationTarget {
+    pub fn is_targeted(&self) -> bool {
+        self.user.is_some() || self.all_in_cluster.is_some()
+    }
+
+    // entries from the config are kept when one is passed so their outputs (e.g.
+    // 1password) are updated too, otherwise they come from the managed secrets
+    pub async fn select(
+        &self,
+        kube_client: &kube::Client,
+        db_connection_details: &DBConnection,
+        db_configs: Vec<DatabaseConfig>,
+    ) -> anyhow::Result<Vec<DatabaseConfig>> {
+        if let Some(cluster) = &self.all_in_cluster {
+            if !host_matches(&db_connection_details.host, cluster) {
+                anyhow::bail!(
+                    "--all-in-cluster={} does not match the connected server {}",
+                    cluster,
+                    db_connection_details.host
+                );
+            }
+        }
+
+        let selected: Vec<DatabaseConfig> =
+            if db_configs.is_empty() || self.all_in_cluster.is_some() {
+                self.managed_entries(kube_client).await?
+            } else {
+                db_configs
+                    .into_iter()
+                    .filter(|db_config| {
+                        self.user
+                            .as_ref()
+                            .is_none_or(|user| *user == db_config.username)
+                    })
+                    .filter(|db_config| {
+                        self.namespace
+                            .as_ref()
+                            .is_none_or(|namespace| *namespace == db_config.namespace)
+                    })
+                    .collect()
+            };
+
+        if selected.is_empty() {
+            anyhow::bail!("no credentials match the rotation target");
+        }
+        Ok(selected)
+    }
+
+    async fn managed_entries(
+        &self,
+        kube_client: &kube::Client,
+    ) -> anyhow::Result<Vec<DatabaseConfig>> {
+        let secrets: Api<Secret> = match &self.namespace {
+            Some(namespace) => Api::namespaced(kube_client.clone(), namespace),
+            None => Api::all(kube_client.clone()),
+        };
+        let managed = secrets
+            .list(&ListParams::default().labels(&format!("{}={}", MANAGED_BY_LABEL, MANAGED_BY)))
+            .await?;
+
+        Ok(managed
+            .items
+            .iter()
+            .filter_map(|secret| {
+                // the annotations carry the entry's server and engine, secrets
+                // created before they were recorded only have the username key
+                let db_config = match secret_entry(secret) {
+                    Some(db_config) => db_config,
+                    None if is_copy(secret) => return None,
+                    None => DatabaseConfig {
+                        username: secret_value(secret, "username")?,
+                        namespace: secret.metadata.namespace.clone().unwrap_or_default(),
+                        secret_name: secret.metadata.name.clone(),
+                        ..Default::default()
+                    },
+                };
+                if self
+                    .user
+                    .as_ref()
+                    .is_some_and(|user| *user != db_config.username)
+                {
+                    return None;
+                }
+                let host = secret_value(secret, "database_host")
+                    .or_else(|| secret_value(secret, "host"))?;
+                if self
+                    .all_in_cluster
+                    .as_ref()
+                    .is_some_and(|cluster| !host_matches(&host, cluster))
+                {
+                    return None;
+                }
+                Some(db_config)
+            })
+            .collect())
+    }
+}
+
+fn is_copy(secret: &Secret) -> bool {
+    secret
+        .metadata
+        .annotations
+        .as_ref()
+        .is_some_and(|annotations| annotations.contains_key(COPY_OF_ANNOTATION))
+}
+
+// prod-pg matches both prod-pg and its service names like prod-pg.db.svc
+fn host_matches(host: &str, cluster: &str) -> bool {
+    host == cluster || host.starts_with(&format!("{}.", cluster))
+}
+
+fn grace_period() -> Result<Option<u64>, ConfigError> {
+    env_number("ROTATION_GRACE_PERIOD_SECONDS")
+}
+
+// the old credential under <key>_previous for every key embedding the password,
+// database_url.N becomes database_url_previous.N. the connection strings log in
+// as the role that still accepts the old password.
+fn previous_values(
+    db_connection_details: &DBConnection,
+    db_config: &DatabaseConfig,
+    login: &str,
+    previous_password: &str,
+) -> BTreeMap<String, String> {
+    if matches!(db_config.secret_format, SecretFormat::ServiceBinding) {
+        return BTreeMap::from([(
+            "password_previous".to_string(),
+            previous_password.to_string(),
+        )]);
+    }
+    let previous_config = DatabaseConfig {
+        username: login.to_string(),
+        secret_files: Vec::new(),
+        ..db_config.clone()
+    };
+    secret_values(db_connection_details, &previous_config, previous_password)
+        .into_iter()
+        .filter(|(_, value)| value.contains(previous_password))
+        .map(|(key, value)| {
+            let key = match key.rsplit_once('.') {
+                Some((name, index)) if !name.is_empty() && index.parse::<usize>().is_ok() => {
+                    format!("{}_previous.{}", name, index)
+                }
+                _ => format!("{}_previous", key),
+            };
+            (key, value)
+        })
+        .collect()
+}
+
+// marks the roles created by keep_previous_password, so only those are dropped
+const PREVIOUS_ROLE_COMMENT: &str = "kube-postgres-bootstrap previous password";
+
+// drops the previous password roles whose grace period is over, only the given
+// one when set
+pub(crate) async fn drop_expired_previous_roles(
+    client: &tokio_postgres::Client,
+    role: Option<&str>,
+) -> Result<(), SqlError> {
+    let expired = client
+        .timed_query(
+            "SELECT rolname FROM pg_roles WHERE shobj_description(oid, 'pg_authid') = $1 AND rolvaliduntil < now() AND ($2::text IS NULL OR rolname = $2);",
+            &[&PREVIOUS_ROLE_COMMENT, &role],
+        )
+        .await?;
+    for row in expired.iter() {
+        let role: String = row.get(0);
+        info!("dropping role {} as its grace period is over", role);
+        client
+            .timed_execute(
+                format!("DROP ROLE IF EXISTS {};", quote_identifier(&role)).as_str(),
+                &[],
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+// postgres only allows a single password per role, so the old password is kept
+// working through a <username>_previous login role that expires after the grace
+// period. sessions of that role switch to the real user so privileges and object
+// ownership are unchanged.
+async fn keep_previous_password(
+    clients: &DBClients,
+    username: &str,
+    previous_password: &str,
+    grace_period: u64,
+) -> Result<(), SqlError> {
+    let previous_username = format!("{}_previous", username);
+    let valid_until: String = clients
+        .admin
+        .timed_query_one(
+            format!(
+                "SELECT (now() + interval '{} seconds')::text;",
+                grace_period
+            )
+            .as_str(),
+            &[],
+        )
+        .await?
+        .get(0);
+
+    let role_exists = clients
+        .admin
+        .timed_query(
+            "SELECT 1 FROM pg_roles WHERE rolname = $1;",
+            &[&previous_username],
+        )
+        .await?;
+
+    info!(
+        "keeping previous password valid until {} as user {}",
+        valid_until, previous_username
+    );
+    let statement = if role_exists.is_empty() {
+        format!(
+            "CREATE ROLE {} WITH LOGIN PASSWORD {} VALID UNTIL {} IN ROLE {}",
+            quote_identifier(&previous_username),
+            quote_literal(previous_password),
+            quote_literal(&valid_until),
+            quote_identifier(username)
+        )
+    } else {
+        format!(
+            "ALTER ROLE {} WITH LOGIN PASSWORD {} VALID UNTIL {}",
+            quote_identifier(&previous_username),
+            quote_literal(previous_password),
+            quote_literal(&valid_until)
+        )
+    };
+    clients.admin.timed_execute(statement.as_str(), &[]).await?;
+    clients
+        .admin
+        .timed_execute(
+            format!(
+                "ALTER ROLE {} SET role = {}",
+                quote_identifier(&previous_username),
+                quote_literal(username)
+            )
+            .as_str(),
+            &[],
+        )
+        .await?;
+
+    clients
+        .admin
+        .timed_execute(
+            format!(
+                "COMMENT ON ROLE {} IS {}",
+                quote_identifier(&previous_username),
+                quote_literal(PREVIOUS_ROLE_COMMENT)
+            )
+            .as_str(),
+            &[],
+        )
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,571 @@
+use serde_json::{json, Value};
+
+use crate::ConfigError;
+
+// the json schema of a config: a list of entries. it mirrors the serde structs
+// (DatabaseConfig and the types of its fields), new fields have to be added here
+// too. pretty printed by `validate --print-schema` for editors and ci.
+pub fn schema() -> Value {
+    let strings = json!({ "type": "array", "items": { "type": "string" } });
+    let string_map = json!({ "type": "object", "additionalProperties": { "type": "string" } });
+    let role_attributes = json!({
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+            "superuser": { "type": "boolean" },
+            "createdb": { "type": "boolean" },
+            "createrole": { "type": "boolean" },
+            "bypassRls": { "type": "boolean" },
+            "validUntil": { "type": "string" },
+        },
+    });
+    let database_options = json!({
+        "owner": { "type": "boolean" },
+        "privileges": strings,
+        "schemaPrivileges": { "type": "object", "additionalProperties": strings },
+        "extensions": strings,
+        "schemas": strings,
+        "encoding": { "type": "string" },
+        "locale": { "type": "string" },
+        "lcCollate": { "type": "string" },
+        "lcCtype": { "type": "string" },
+        "localeProvider": { "type": "string" },
+        "icuLocale": { "type": "string" },
+        "connectionLimit": { "type": "integer" },
+        "allowConnections": { "type": "boolean" },
+        "cloneFrom": { "type": "string" },
+        "template": { "type": "string" },
+        "seed": { "type": "string" },
+        "initSql": strings,
+    });
+    let mut database_spec_properties = database_options.clone();
+    database_spec_properties["name"] = json!({ "type": "string" });
+    let grant_schemas = json!({
+        "type": "object",
+        "additionalProperties": false,
+        "properties": { "schemas": strings },
+    });
+
+    // split in two, one json! of every field exceeds the macro recursion limit
+    let mut properties = json!({
+        "username": { "type": "string" },
+        "databases": {
+            "type": "array",
+            "items": {
+                "anyOf": [
+                    { "type": "string" },
+                    {
+                        "type": "object",
+                        "additionalProperties": false,
+                        "required": ["name"],
+                        "properties": database_spec_properties,
+                    },
+                ],
+            },
+        },
+        "namespace": { "type": "string" },
+        "grantExisting": grant_schemas,
+        "publications": {
+            "type": "array",
+            "items": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["database", "name"],
+                "properties": {
+                    "database": { "type": "string" },
+                    "name": { "type": "string" },
+                    "tables": strings,
+                    "allTables": { "type": "boolean" },
+                },
+            },
+        },
+        "pgaudit": string_map,
+        "ownSchema": { "type": "boolean" },
+        "rowLevelSecurity": {
+            "type": "array",
+            "items": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["database", "tables", "tenantColumn"],
+                "properties": {
+                    "database": { "type": "string" },
+                    "tables": strings,
+                    "tenantColumn": { "type": "string" },
+                    "tenant": { "type": "string" },
+                },
+            },
+        },
+        "databaseOptions": {
+            "type": "object",
+            "additionalProperties": {
+                "type": "object",
+                "additionalProperties": false,
+                "properties": database_options,
+            },
+        },
+        "secretFormat": { "enum": ["default", "serviceBinding"] },
+        "configMap": { "type": "boolean" },
+        "csi": {
+            "type": "object",
+            "additionalProperties": false,
+            "required": ["provider", "path"],
+            "properties": {
+                "provider": { "enum": ["vault", "aws"] },
+                "path": { "type": "string" },
+                "mount": { "type": "string" },
+                "role": { "type": "string" },
+            },
+        },
+        "pushSecret": {
+            "type": "object",
+            "additionalProperties": false,
+            "required": ["secretStore", "remoteKey"],
+            "properties": {
+                "secretStore": { "type": "string" },
+                "secretStoreKind": { "type": "string" },
+                "remoteKey": { "type": "string" },
+                "refreshInterval": { "type": "string" },
+            },
+        },
+        "grafanaDatasource": {
+            "type": "object",
+            "additionalProperties": false,
+            "properties": {
+                "namespace": { "type": "string" },
+                "name": { "type": "string" },
+                "database": { "type": "string" },
+                "label": { "type": "string" },
+            },
+        },
+        "onePassword": {
+            "type": "object",
+            "additionalProperties": false,
+            "required": ["vault"],
+            "properties": {
+                "vault": { "type": "string" },
+                "title": { "type": "string" },
+            },
+        },
+    });
+    let more_properties = json!({
+        "createSecret": { "type": "boolean" },
+        "manageDatabase": { "type": "boolean" },
+        "passwordSecretRef": {
+            "type": "object",
+            "additionalProperties": false,
+            "required": ["name"],
+            "properties": {
+                "name": { "type": "string" },
+                "key": { "type": "string" },
+                "namespace": { "type": "string" },
+            },
+        },
+        "timeout": { "type": "integer", "minimum": 0 },
+        "connectionLimit": { "type": "integer" },
+        "users": {
+            "type": "array",
+            "items": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["username"],
+                "properties": {
+                    "username": { "type": "string" },
+                    "pgaudit": string_map,
+                    "connectionLimit": { "type": "integer" },
+                    "roleAttributes": role_attributes,
+                },
+            },
+        },
+        "grantsOn": {
+            "type": "array",
+            "items": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["entry", "database", "privileges"],
+                "properties": {
+                    "entry": { "type": "string" },
+                    "database": { "type": "string" },
+                    "privileges": strings,
+                    "schemas": strings,
+                },
+            },
+        },
+        "host": { "type": "string" },
+        "port": { "type": "integer", "minimum": 0, "maximum": 65535 },
+        "adminSecret": { "type": "string" },
+        "passwordPolicy": {
+            "type": "object",
+            "additionalProperties": false,
+            "properties": {
+                "length": { "type": "integer", "minimum": 0 },
+                "symbols": { "type": "boolean" },
+                "urlSafe": { "type": "boolean" },
+                "passphraseWords": { "type": "integer", "minimum": 0 },
+            },
+        },
+        "sslMode": { "type": "string" },
+        "secretTemplate": {
+            "anyOf": [
+                { "enum": ["libpq", "database-url", "rails", "django"] },
+                string_map,
+            ],
+        },
+        "secretLabels": string_map,
+        "secretAnnotations": string_map,
+        "secretName": { "type": "string" },
+        "secretOwner": {
+            "type": "object",
+            "additionalProperties": false,
+            "required": ["apiVersion", "kind", "name"],
+            "properties": {
+                "apiVersion": { "type": "string" },
+                "kind": { "type": "string" },
+                "name": { "type": "string" },
+                "blockOwnerDeletion": { "type": "boolean" },
+            },
+        },
+        "readonly": { "type": "boolean" },
+        "roleAttributes": role_attributes,
+        "memberOf": strings,
+        "secretFiles": { "type": "array", "items": { "enum": ["pgpass", "dotenv"] } },
+        "engine": {
+            "enum": ["postgres", "mysql", "mariadb", "cockroachdb", "cockroach"],
+        },
+        "secretStores": {
+            "type": "array",
+            "items": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["type"],
+                "properties": {
+                    "type": { "enum": ["vault", "aws", "gcp"] },
+                    "mount": { "type": "string" },
+                    "path": { "type": "string" },
+                    "name": { "type": "string" },
+                    "project": { "type": "string" },
+                    "secret": { "type": "string" },
+                },
+            },
+        },
+        "extraNamespaces": strings,
+        "iamAuth": { "type": "boolean" },
+    });
+    properties
+        .as_object_mut()
+        .unwrap()
+        .extend(more_properties.as_object().unwrap().clone());
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "kube-postgres-bootstrap config",
+        "type": "array",
+        "items": {
+            "type": "object",
+            "additionalProperties": false,
+            "required": ["databases", "namespace"],
+            "properties": properties,
+        },
+    })
+}
+
+// checks the config against the schema before it's deserialized, so every
+// mistake is reported at once with where it is instead of serde's first error.
+// only the keywords used by schema() are supported.
+pub fn validate(document: &Value) -> Result<(), ConfigError> {
+    let schema = schema();
+    let mut errors = Vec::new();
+    match document.as_array() {
+        Some(entries) => {
+            for (index, entry) in entries.iter().enumerate() {
+                let name = match entry.get("username").and_then(Value::as_str) {
+                    Some(username) => format!("entry {} ({})", index, username),
+                    None => format!("entry {}", index),
+                };
+                let mut entry_errors = Vec::new();
+                check(&schema["items"], entry, "", &mut entry_errors);
+                errors.extend(
+                    entry_errors
+                        .into_iter()
+                        .map(|error| format!("{}: {}", name, error)),
+                );
+            }
+        }
+        None => errors.push(format!(
+            "config: expected a list of entries, got {}",
+            type_name(document)
+        )),
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigError::Schema(errors))
+    }
+}
+
+fn check(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    if let Some(alternatives) = schema["anyOf"].as_array() {
+        let mut first_errors = None;
+        for alternative in alternatives.iter() {
+            let mut alternative_errors = Vec::new();
+            check(alternative, value, path, &mut alternative_errors);
+            if alternative_errors.is_empty() {
+                return;
+            }
+            // the errors of the alternative of the same type are the useful ones
+            if first_errors.is_none() && matches_type(alternative, value) {
+                first_errors = Some(alternative_errors);
+            }
+        }
+        match first_errors {
+            Some(alternative_errors) => errors.extend(alternative_errors),
+            None => errors.push(format!(
+                "{}expected {}, got {}",
+                at(path),
+                alternatives
+                    .iter()
+                    .map(describe)
+                    .collect::<Vec<_>>()
+                    .join(" or "),
+                type_name(value)
+            )),
+        }
+        return;
+    }
+
+    if let Some(allowed) = schema["enum"].as_array() {
+        if !allowed.contains(value) {
+            errors.push(format!(
+                "{}expected {}, got {}",
+                at(path),
+                describe(schema),
+                value
+            ));
+        }
+        return;
+    }
+
+    if !matches_type(schema, value) {
+        errors.push(format!(
+            "{}expected {}, got {}",
+            at(path),
+            describe(schema),
+            type_name(value)
+        ));
+        return;
+    }
+
+    match value {
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if let Some(minimum) = schema["minimum"]
+                .as_f64()
+                .filter(|minimum| number < *minimum)
+            {
+                errors.push(format!("{}must be at least {}", at(path), minimum));
+            }
+            if let Some(maximum) = schema["maximum"]
+                .as_f64()
+                .filter(|maximum| number > *maximum)
+            {
+                errors.push(format!("{}must be at most {}", at(path), maximum));
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                check(
+                    &schema["items"],
+                    item,
+                    &format!("{}[{}]", path, index),
+                    errors,
+                );
+            }
+        }
+        Value::Object(fields) => {
+            let properties = schema["properties"].as_object();
+            for required in schema["required"].as_array().into_iter().flatten() {
+                let required = required.as_str().unwrap_or_default();
+                if !fields.contains_key(required) {
+                    errors.push(format!("{}missing required field {}", at(path), required));
+                }
+            }
+            for (field, field_value) in fields.iter() {
+                let field_path = if path.is_empty() {
+                    field.clone()
+                } else {
+                    format!("{}.{}", path, field)
+                };
+                match properties.and_then(|properties| properties.get(field)) {
+                    Some(field_schema) => check(field_schema, field_value, &field_path, errors),
+                    None => match &schema["additionalProperties"] {
+                        Value::Bool(false) => {
+                            let known = properties
+                                .into_iter()
+                                .flat_map(|properties| properties.keys());
+                            errors.push(match closest(field, known) {
+                                Some(suggestion) => format!(
+                                    "{}unknown field, did you mean {}?",
+                                    at(&field_path),
+                                    suggestion
+                                ),
+                                None => format!("{}unknown field", at(&field_path)),
+                            });
+                        }
+                        Value::Object(_) => check(
+                            &schema["additionalProperties"],
+                            field_value,
+                            &field_path,
+                            errors,
+                        ),
+                        _ => {}
+                    },
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// where an error is within the entry, empty for the entry itself
+fn at(path: &str) -> String {
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!("{}: ", path)
+    }
+}
+
+fn matches_type(schema: &Value, value: &Value) -> bool {
+    match schema["type"].as_str() {
+        Some("integer") => value.is_i64() || value.is_u64(),
+        Some(expected) => type_name(value) == expected,
+        None => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn describe(schema: &Value) -> String {
+    match (&schema["enum"], schema["type"].as_str()) {
+        (Value::Array(allowed), _) => format!(
+            "one of {}",
+            allowed
+                .iter()
+                .map(Value::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        (_, Some("array")) => "a list".to_string(),
+        (_, Some("object")) => "an object".to_string(),
+        (_, Some("integer")) => "an integer".to_string(),
+        (_, Some(expected)) => format!("a {}", expected),
+        _ => "a value".to_string(),
+    }
+}
+
+// the known field a misspelled one was most likely meant to be
+fn closest<'a>(field: &str, known: impl Iterator<Item = &'a String>) -> Option<&'a String> {
+    known
+        .map(|candidate| (edit_distance(field, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(document: Value) -> Vec<String> {
+        match validate(&document) {
+            Ok(()) => Vec::new(),
+            Err(ConfigError::Schema(errors)) => errors,
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn accepts_a_valid_config() {
+        let document = json!([
+            {
+                "username": "app",
+                "namespace": "default",
+                "databases": ["app", { "name": "reporting", "owner": true }],
+                "timeout": 30,
+            },
+        ]);
+        assert_eq!(errors(document), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rejects_a_config_that_is_not_a_list() {
+        assert_eq!(
+            errors(json!({ "username": "app" })),
+            ["config: expected a list of entries, got object"]
+        );
+    }
+
+    #[test]
+    fn suggests_misspelled_fields() {
+        assert_eq!(
+            errors(json!([{ "username": "app", "namspace": "default", "databases": [] }])),
+            [
+                "entry 0 (app): missing required field namespace",
+                "entry 0 (app): namspace: unknown field, did you mean namespace?",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_every_error_with_its_path() {
+        let document = json!([
+            { "username": "app", "namespace": "default", "databases": "app" },
+            {
+                "username": "other",
+                "namespace": "default",
+                "secretFormat": "env",
+                "timeout": -1,
+                "databases": [{ "owner": true }],
+            },
+        ]);
+        assert_eq!(
+            errors(document),
+            [
+                "entry 0 (app): databases: expected a list, got string",
+                "entry 1 (other): secretFormat: expected one of \"default\", \"serviceBinding\", got \"env\"",
+                "entry 1 (other): timeout: must be at least 0",
+                "entry 1 (other): databases[0]: missing required field name",
+            ]
+        );
+    }
+
+    #[test]
+    fn edit_distance_counts_single_edits() {
+        assert_eq!(edit_distance("namespace", "namespace"), 0);
+        assert_eq!(edit_distance("namspace", "namespace"), 1);
+        assert_eq!(edit_distance("usernme", "username"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}
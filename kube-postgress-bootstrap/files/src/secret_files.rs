@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+
+// additional secret keys holding the credentials as a whole file, for containers
+// that can't assemble them from the individual keys
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SecretFile {
+    // .pgpass, one line per host and database. libpq only reads it with 0600
+    // permissions, so mount it with defaultMode: 0600 (384).
+    Pgpass,
+    // .env, every other key of the secret as an upper case env var
+    Dotenv,
+}
+
+impl SecretFile {
+    pub fn key(&self) -> &'static str {
+        match self {
+            SecretFile::Pgpass => ".pgpass",
+            SecretFile::Dotenv => ".env",
+        }
+    }
+}
+
+// hosts and ports are comma separated lists for multi-host servers, a single
+// port applies to every host
+pub fn pgpass(
+    hosts: &str,
+    ports: &str,
+    databases: &[String],
+    username: &str,
+    password: &str,
+) -> String {
+    let ports: Vec<&str> = ports.split(',').collect();
+    let mut lines = String::new();
+    for (i, host) in hosts.split(',').enumerate() {
+        let port = *ports.get(i).unwrap_or(&ports[0]);
+        for db in databases.iter() {
+            lines.push_str(
+                &[host.trim(), port, db, username, password]
+                    .map(pgpass_escape)
+                    .join(":"),
+            );
+            lines.push('\n');
+        }
+    }
+    lines
+}
+
+fn pgpass_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(':', "\\:")
+}
+
+// database_url.0 becomes DATABASE_URL_0, keys that already are env vars (e.g.
+// from a secretTemplate) are kept as they are
+pub fn dotenv(values: &BTreeMap<String, String>) -> String {
+    values
+        .iter()
+        .map(|(key, value)| {
+            let name: String = key
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() {
+                        c.to_ascii_uppercase()
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            format!("{}={}\n", name, dotenv_quote(value))
+        })
+        .collect()
+}
+
+// single quotes are taken literally by the common dotenv parsers, so $ in a
+// password isn't expanded
+fn dotenv_quote(value: &str) -> String {
+    if !value.contains('\'') && !value.contains('\n') {
+        return format!("'{}'", value);
+    }
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('$', "\\$")
+            .replace('\n', "\\n")
+    )
+}
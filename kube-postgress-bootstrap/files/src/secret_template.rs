@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+// the keys of the secret as templates over the default keys (e.g.
+// {"PGPASSWORD": "{password}", "DATABASE_URL": "{database_uri.0}"}), plus {sslmode}.
+// one key has to be just {password}, rotate and verify read the password from it.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(untagged)]
+pub enum SecretTemplate {
+    Preset(Preset),
+    Keys(BTreeMap<String, String>),
+}
+
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    // the env vars libpq and psql read
+    Libpq,
+    DatabaseUrl,
+    // the env vars the rails and django database settings are usually read from
+    Rails,
+    Django,
+}
+
+impl SecretTemplate {
+    fn keys(&self) -> BTreeMap<String, String> {
+        let keys: &[(&str, &str)] = match self {
+            SecretTemplate::Keys(keys) => return keys.clone(),
+            SecretTemplate::Preset(Preset::Libpq) => &[
+                ("PGHOST", "{database_host}"),
+                ("PGPORT", "{database_port}"),
+                ("PGDATABASE", "{database.0}"),
+                ("PGUSER", "{username}"),
+                ("PGPASSWORD", "{password}"),
+                ("PGSSLMODE", "{sslmode}"),
+            ],
+            SecretTemplate::Preset(Preset::DatabaseUrl) => &[
+                ("DATABASE_URL", "{database_uri.0}"),
+                ("DATABASE_PASSWORD", "{password}"),
+            ],
+            SecretTemplate::Preset(Preset::Rails) => &[
+                ("DATABASE_URL", "{database_uri.0}"),
+                ("DATABASE_HOST", "{database_host}"),
+                ("DATABASE_PORT", "{database_port}"),
+                ("DATABASE_NAME", "{database.0}"),
+                ("DATABASE_USERNAME", "{username}"),
+                ("DATABASE_PASSWORD", "{password}"),
+            ],
+            SecretTemplate::Preset(Preset::Django) => &[
+                ("DATABASE_URL", "{database_uri.0}"),
+                ("DB_HOST", "{database_host}"),
+                ("DB_PORT", "{database_port}"),
+                ("DB_NAME", "{database.0}"),
+                ("DB_USER", "{username}"),
+                ("DB_PASSWORD", "{password}"),
+            ],
+        };
+        keys.iter()
+            .map(|(key, template)| (key.to_string(), template.to_string()))
+            .collect()
+    }
+
+    pub fn password_key(&self) -> Option<String> {
+        self.keys()
+            .into_iter()
+            .find(|(_, template)| template == "{password}")
+            .map(|(key, _)| key)
+    }
+
+    // every placeholder has to be one of the default keys of an entry with this
+    // many databases
+    pub fn validate(&self, databases: usize) -> Result<(), String> {
+        if self.password_key().is_none() {
+            return Err("secretTemplate needs a key holding just {password}".to_string());
+        }
+        for (key, template) in self.keys().iter() {
+            for placeholder in placeholders(template) {
+                let known = match placeholder.split_once('.') {
+                    Some(("database" | "database_url" | "database_uri", index)) => index
+                        .parse::<usize>()
+                        .map(|index| index < databases)
+                        .unwrap_or(false),
+                    Some(_) => false,
+                    None => matches!(
+                        placeholder,
+                        "database_host" | "database_port" | "username" | "password" | "sslmode"
+                    ),
+                };
+                if !known {
+                    return Err(format!(
+                        "secretTemplate key {} uses unknown placeholder {{{}}}",
+                        key, placeholder
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn render(&self, values: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+        self.keys()
+            .into_iter()
+            .map(|(key, template)| (key, render(&template, values)))
+            .collect()
+    }
+}
+
+// a single pass, so braces in a substituted password are left alone
+fn render(template: &str, values: &BTreeMap<String, String>) -> String {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        match rest[start + 1..].split_once('}') {
+            Some((name, after)) if values.contains_key(name) => {
+                rendered.push_str(&values[name]);
+                rest = after;
+            }
+            _ => {
+                rendered.push('{');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+fn placeholders(template: &str) -> Vec<&str> {
+    template
+        .split('{')
+        .skip(1)
+        .filter_map(|part| part.split_once('}').map(|(name, _)| name))
+        .collect()
+}
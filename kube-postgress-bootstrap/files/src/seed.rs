@@ -0,0 +1,298 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Seek, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::Context;
+
+use crate::{oci, source};
+
+// statements are sent in batches of about this size, so a large seed is never
+// held in memory as a whole
+const CHUNK_SIZE: usize = 1 << 20;
+
+static DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+
+// opens a seed from a local path or an s3://, https:// or oci:// location. remote
+// seeds are streamed to a temporary file first, gzip'd ones are decompressed while
+// reading.
+pub async fn open(location: &str) -> anyhow::Result<SqlChunks> {
+    let file = if location.contains("://") {
+        download(location)
+            .await
+            .with_context(|| format!("failed to download {}", location))?
+    } else {
+        File::open(location)?
+    };
+    let mut reader = BufReader::new(file);
+    let reader: Box<dyn BufRead + Send> = if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Box::new(BufReader::new(flate2::read::GzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    };
+    Ok(SqlChunks::new(location, reader))
+}
+
+// the file is unlinked once written, it goes away with the handle
+async fn download(location: &str) -> anyhow::Result<File> {
+    let path = std::env::temp_dir().join(format!(
+        "kube-postgres-bootstrap-seed-{}-{}",
+        std::process::id(),
+        DOWNLOADS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    std::fs::remove_file(&path)?;
+
+    if location.starts_with("s3://") {
+        let mut body = source::s3_object(location).await?.body;
+        while let Some(bytes) = body.try_next().await? {
+            file.write_all(&bytes)?;
+        }
+    } else if location.starts_with("https://") || location.starts_with("http://") {
+        let mut response = reqwest::get(location).await?.error_for_status()?;
+        while let Some(bytes) = response.chunk().await? {
+            file.write_all(&bytes)?;
+        }
+    } else if location.starts_with("oci://") {
+        file.write_all(&oci::read_artifact(location, None).await?)?;
+    } else {
+        anyhow::bail!("unsupported seed location {}", location);
+    }
+    file.rewind()?;
+    Ok(file)
+}
+
+// where the scanner is within a script, statements only end on a ; outside of
+// quotes, comments and dollar quoted bodies
+enum Lexer {
+    Code,
+    // the quote character, and whether backslashes escape it as in E'...'
+    Quoted(u8, bool),
+    LineComment,
+    BlockComment(usize),
+    Dollar(Vec<u8>),
+}
+
+// the statements of a script in chunks of whole statements. a script that
+// doesn't end in a ; has its last statement in the final chunk.
+pub struct SqlChunks {
+    location: String,
+    reader: Option<Box<dyn BufRead + Send>>,
+    buffer: Vec<u8>,
+    // the end of the last complete statement in buffer
+    boundary: usize,
+    lexer: Lexer,
+}
+
+impl SqlChunks {
+    fn new(location: &str, reader: Box<dyn BufRead + Send>) -> SqlChunks {
+        SqlChunks {
+            location: location.to_string(),
+            reader: Some(reader),
+            buffer: Vec::new(),
+            boundary: 0,
+            lexer: Lexer::Code,
+        }
+    }
+
+    fn take(&mut self, end: usize) -> anyhow::Result<String> {
+        let chunk: Vec<u8> = self.buffer.drain(..end).collect();
+        self.boundary = 0;
+        String::from_utf8(chunk).with_context(|| format!("{} is not valid utf-8", self.location))
+    }
+
+    // scans the line at the end of buffer starting at start, tracking the
+    // boundary of the last statement
+    fn scan_line(&mut self, start: usize) {
+        let bytes = &self.buffer;
+        let mut i = start;
+        while i < bytes.len() {
+            let byte = bytes[i];
+            let next = bytes.get(i + 1).copied();
+            match &self.lexer {
+                Lexer::Code => match byte {
+                    b'\'' => {
+                        let escaped = i > 0
+                            && matches!(bytes[i - 1], b'e' | b'E')
+                            && (i < 2 || !is_identifier(bytes[i - 2]));
+                        self.lexer = Lexer::Quoted(byte, escaped);
+                    }
+                    b'"' => self.lexer = Lexer::Quoted(byte, false),
+                    b'-' if next == Some(b'-') => {
+                        self.lexer = Lexer::LineComment;
+                        i += 1;
+                    }
+                    b'/' if next == Some(b'*') => {
+                        self.lexer = Lexer::BlockComment(1);
+                        i += 1;
+                    }
+                    // $tag$, but not a $1 parameter or a $ within an identifier
+                    b'$' if i == 0 || !is_identifier(bytes[i - 1]) => {
+                        let tag_end = bytes[i + 1..]
+                            .iter()
+                            .position(|b| !is_identifier(*b) || *b == b'$')
+                            .map(|position| i + 1 + position);
+                        if let Some(tag_end) = tag_end.filter(|tag_end| bytes[*tag_end] == b'$') {
+                            let tag = bytes[i + 1..tag_end].to_vec();
+                            if tag.first().is_none_or(|b| !b.is_ascii_digit()) {
+                                self.lexer = Lexer::Dollar(tag);
+                                i = tag_end;
+                            }
+                        }
+                    }
+                    b';' => self.boundary = i + 1,
+                    _ => {}
+                },
+                Lexer::Quoted(quote, escaped) => {
+                    if *escaped && byte == b'\\' {
+                        i += 1;
+                    } else if byte == *quote {
+                        // a doubled quote is part of the string
+                        if next == Some(*quote) {
+                            i += 1;
+                        } else {
+                            self.lexer = Lexer::Code;
+                        }
+                    }
+                }
+                Lexer::LineComment => {
+                    if byte == b'\n' {
+                        self.lexer = Lexer::Code;
+                    }
+                }
+                Lexer::BlockComment(depth) => {
+                    let depth = *depth;
+                    if byte == b'*' && next == Some(b'/') {
+                        self.lexer = match depth {
+                            1 => Lexer::Code,
+                            depth => Lexer::BlockComment(depth - 1),
+                        };
+                        i += 1;
+                    } else if byte == b'/' && next == Some(b'*') {
+                        self.lexer = Lexer::BlockComment(depth + 1);
+                        i += 1;
+                    }
+                }
+                Lexer::Dollar(tag) => {
+                    let end = i + 1 + tag.len();
+                    if byte == b'$'
+                        && bytes.get(i + 1..end) == Some(tag.as_slice())
+                        && bytes.get(end) == Some(&b'$')
+                    {
+                        self.lexer = Lexer::Code;
+                        i = end;
+                    }
+                }
+            }
+            i += 1;
+        }
+    }
+}
+
+impl Iterator for SqlChunks {
+    type Item = anyhow::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.boundary >= CHUNK_SIZE {
+                return Some(self.take(self.boundary));
+            }
+            let reader = match self.reader.as_mut() {
+                Some(reader) => reader,
+                None if self.buffer.iter().all(u8::is_ascii_whitespace) => return None,
+                None => return Some(self.take(self.buffer.len())),
+            };
+            let start = self.buffer.len();
+            match reader.read_until(b'\n', &mut self.buffer) {
+                Ok(0) => self.reader = None,
+                Ok(_) => self.scan_line(start),
+                Err(e) => {
+                    self.reader = None;
+                    return Some(
+                        Err(e).with_context(|| format!("failed to read {}", self.location)),
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn is_identifier(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'$' || byte >= 0x80
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(sql: &str) -> SqlChunks {
+        SqlChunks::new(
+            "seed.sql",
+            Box::new(std::io::Cursor::new(sql.as_bytes().to_vec())),
+        )
+    }
+
+    // the end of every statement found while reading the script line by line
+    fn boundaries(sql: &str) -> Vec<usize> {
+        let mut chunks = chunks("");
+        let mut boundaries = Vec::new();
+        for line in sql.split_inclusive('\n') {
+            let start = chunks.buffer.len();
+            chunks.buffer.extend_from_slice(line.as_bytes());
+            chunks.scan_line(start);
+            if boundaries.last() != Some(&chunks.boundary) && chunks.boundary > 0 {
+                boundaries.push(chunks.boundary);
+            }
+        }
+        boundaries
+    }
+
+    #[test]
+    fn statements_end_on_semicolons() {
+        let sql = "CREATE TABLE a (id int);\nINSERT INTO a VALUES (1);\n";
+        assert_eq!(boundaries(sql), [24, 50]);
+    }
+
+    #[test]
+    fn semicolons_in_strings_and_comments_are_skipped() {
+        let sql = "INSERT INTO a VALUES ('x;''y', E'\\';', \"b;c\");\n-- no; end\n/* no; /* nested; */ end; */ SELECT 1;\n";
+        let ends: Vec<&str> = boundaries(sql).iter().map(|end| &sql[..*end]).collect();
+        assert_eq!(ends.len(), 2);
+        assert!(ends[0].ends_with("\"b;c\");"));
+        assert!(ends[1].ends_with("SELECT 1;"));
+    }
+
+    #[test]
+    fn dollar_quoted_bodies_span_lines() {
+        let sql = "CREATE FUNCTION f() RETURNS int AS $body$\nBEGIN\n  RETURN 1;\nEND;\n$body$ LANGUAGE plpgsql;\nSELECT $1;\n";
+        let ends: Vec<&str> = boundaries(sql).iter().map(|end| &sql[..*end]).collect();
+        assert_eq!(ends.len(), 2);
+        assert!(ends[0].ends_with("LANGUAGE plpgsql;"));
+    }
+
+    #[test]
+    fn chunks_cover_the_whole_script() {
+        let statement = "INSERT INTO a VALUES ('a row of seed data');\n";
+        let sql = format!(
+            "{}SELECT 1",
+            statement.repeat(CHUNK_SIZE / statement.len() * 3)
+        );
+        let chunks: Vec<String> = chunks(&sql).collect::<anyhow::Result<_>>().unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks[..chunks.len() - 1]
+            .iter()
+            .all(|chunk| chunk.trim_end().ends_with(';')));
+        assert_eq!(chunks.concat(), sql);
+    }
+
+    #[test]
+    fn empty_scripts_have_no_chunks() {
+        assert_eq!(chunks("").count(), 0);
+        assert_eq!(chunks("\n  \n").count(), 0);
+    }
+}
@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::Notify,
+};
+use tracing::warn;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static NOTIFY: Notify = Notify::const_new();
+
+// catches SIGTERM and SIGINT so an evicted pod finishes the entries in flight
+// instead of leaving a user without its secret. new entries aren't started and
+// the long running modes stop waiting for work. a second signal exits right away.
+pub fn listen() {
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    let mut interrupt = signal(SignalKind::interrupt()).expect("failed to listen for SIGINT");
+    tokio::spawn(async move {
+        loop {
+            let name = tokio::select! {
+                _ = terminate.recv() => "SIGTERM",
+                _ = interrupt.recv() => "SIGINT",
+            };
+            if REQUESTED.swap(true, Ordering::Relaxed) {
+                warn!("received a second {}, exiting", name);
+                std::process::exit(1);
+            }
+            warn!(
+                "received {}, finishing the entries in flight before exiting",
+                name
+            );
+            NOTIFY.notify_waiters();
+        }
+    });
+}
+
+// stops the long running modes like a signal would, e.g. once the leader lease is lost
+pub fn request() {
+    REQUESTED.store(true, Ordering::Relaxed);
+    NOTIFY.notify_waiters();
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+// resolves once a shutdown was requested, for selecting against waits
+pub async fn wait() {
+    let notified = NOTIFY.notified();
+    if requested() {
+        return;
+    }
+    notified.await
+}
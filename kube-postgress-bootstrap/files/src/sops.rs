@@ -0,0 +1,219 @@
+use std::{env, io::Read};
+
+use aes_gcm::{
+    aead::{consts::U32, Aead, KeyInit, Payload},
+    aes::Aes256,
+    AesGcm,
+};
+use anyhow::Context;
+use base64::Engine;
+use sha2::{Digest, Sha512};
+use tracing::info;
+
+// sops encrypts values with AES-256-GCM using a 32 byte nonce
+type SopsCipher = AesGcm<Aes256, U32>;
+
+// decrypts the config in-process if it's a sops encrypted file, otherwise returns it
+// unchanged. sops can't encrypt a top level json array, so configs have to be
+// encrypted as a whole with `sops --encrypt --input-type binary`, which stores the
+// file under a single data key.
+pub async fn decrypt_if_encrypted(config: String) -> anyhow::Result<String> {
+    let mut document: serde_json::Value = match serde_json::from_str(&config) {
+        Ok(serde_json::Value::Object(document)) if document.contains_key("sops") => {
+            serde_json::Value::Object(document)
+        }
+        _ => return Ok(config),
+    };
+    info!("decrypting sops encrypted config");
+
+    let metadata = document
+        .as_object_mut()
+        .and_then(|document| document.remove("sops"))
+        .context("sops config has no metadata")?;
+    let key = data_key(&metadata).await?;
+    let mac_only_encrypted = metadata["mac_only_encrypted"].as_bool().unwrap_or(false);
+
+    let mut hasher = Sha512::new();
+    decrypt_tree(
+        &mut document,
+        &key,
+        &mut Vec::new(),
+        &mut hasher,
+        mac_only_encrypted,
+    )?;
+    verify_mac(&metadata, &key, hasher)?;
+
+    match document.get("data") {
+        Some(serde_json::Value::String(data)) if document.as_object().unwrap().len() == 1 => {
+            Ok(data.clone())
+        }
+        _ => anyhow::bail!("sops encrypted configs must be encrypted with --input-type binary"),
+    }
+}
+
+fn decrypt_tree(
+    value: &mut serde_json::Value,
+    key: &[u8],
+    path: &mut Vec<String>,
+    hasher: &mut Sha512,
+    mac_only_encrypted: bool,
+) -> anyhow::Result<()> {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (name, value) in map.iter_mut() {
+                path.push(name.clone());
+                decrypt_tree(value, key, path, hasher, mac_only_encrypted)?;
+                path.pop();
+            }
+        }
+        // list items share the path of the list itself
+        serde_json::Value::Array(items) => {
+            for value in items.iter_mut() {
+                decrypt_tree(value, key, path, hasher, mac_only_encrypted)?;
+            }
+        }
+        serde_json::Value::String(encrypted) if encrypted.starts_with("ENC[") => {
+            let additional_data = format!("{}:", path.join(":"));
+            let (plaintext, value_type) = decrypt_value(encrypted, key, &additional_data)?;
+            hasher.update(plaintext.as_bytes());
+            *value = match value_type.as_str() {
+                "int" | "float" => serde_json::from_str(&plaintext)?,
+                "bool" => serde_json::Value::Bool(plaintext == "True"),
+                _ => serde_json::Value::String(plaintext),
+            };
+        }
+        _ if mac_only_encrypted => {}
+        serde_json::Value::String(plaintext) => hasher.update(plaintext.as_bytes()),
+        serde_json::Value::Number(number) => hasher.update(number.to_string().as_bytes()),
+        serde_json::Value::Bool(true) => hasher.update(b"True"),
+        serde_json::Value::Bool(false) => hasher.update(b"False"),
+        serde_json::Value::Null => {}
+    }
+
+    Ok(())
+}
+
+// decrypts ENC[AES256_GCM,data:...,iv:...,tag:...,type:...], returning the
+// plaintext and its type
+fn decrypt_value(
+    encrypted: &str,
+    key: &[u8],
+    additional_data: &str,
+) -> anyhow::Result<(String, String)> {
+    let fields = encrypted
+        .strip_prefix("ENC[AES256_GCM,")
+        .and_then(|fields| fields.strip_suffix(']'))
+        .context("unsupported sops encrypted value")?;
+
+    let (mut data, mut iv, mut tag, mut value_type) = (None, None, None, "str");
+    for field in fields.split(',') {
+        match field.split_once(':') {
+            Some(("data", value)) => data = Some(value),
+            Some(("iv", value)) => iv = Some(value),
+            Some(("tag", value)) => tag = Some(value),
+            Some(("type", value)) => value_type = value,
+            _ => {}
+        }
+    }
+    let decode = |value: Option<&str>, name: &str| {
+        base64::engine::general_purpose::STANDARD
+            .decode(value.with_context(|| format!("sops value has no {}", name))?)
+            .with_context(|| format!("sops value has an invalid {}", name))
+    };
+    let mut ciphertext = decode(data, "data")?;
+    ciphertext.extend(decode(tag, "tag")?);
+    let iv = decode(iv, "iv")?;
+    if iv.len() != 32 {
+        anyhow::bail!("sops value has an invalid iv length");
+    }
+
+    let plaintext = SopsCipher::new_from_slice(key)
+        .map_err(|_| anyhow::anyhow!("sops data key has an invalid length"))?
+        .decrypt(
+            iv.as_slice().into(),
+            Payload {
+                msg: &ciphertext,
+                aad: additional_data.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow::anyhow!("failed to decrypt sops value"))?;
+
+    Ok((String::from_utf8(plaintext)?, value_type.to_string()))
+}
+
+// the mac is a sha512 over every value, encrypted with lastmodified as additional data
+fn verify_mac(metadata: &serde_json::Value, key: &[u8], hasher: Sha512) -> anyhow::Result<()> {
+    let mac = metadata["mac"].as_str().context("sops config has no mac")?;
+    let last_modified = metadata["lastmodified"]
+        .as_str()
+        .context("sops config has no lastmodified")?;
+    let (expected, _) = decrypt_value(mac, key, last_modified)?;
+
+    if format!("{:X}", hasher.finalize()) != expected {
+        anyhow::bail!("sops mac mismatch, the config has been tampered with");
+    }
+    Ok(())
+}
+
+// decrypts the data key with the first age or kms master key that works
+async fn data_key(metadata: &serde_json::Value) -> anyhow::Result<Vec<u8>> {
+    let mut errors = Vec::new();
+
+    for age_key in metadata["age"].as_array().into_iter().flatten() {
+        match decrypt_age_key(age_key["enc"].as_str().unwrap_or_default()) {
+            Ok(key) => return Ok(key),
+            Err(e) => errors.push(format!("age: {}", e)),
+        }
+    }
+    for kms_key in metadata["kms"].as_array().into_iter().flatten() {
+        match decrypt_kms_key(kms_key).await {
+            Ok(key) => return Ok(key),
+            Err(e) => errors.push(format!("kms: {}", e)),
+        }
+    }
+
+    anyhow::bail!(
+        "failed to decrypt sops data key with any master key: {}",
+        errors.join(", ")
+    )
+}
+
+// identities come from SOPS_AGE_KEY or the SOPS_AGE_KEY_FILE keys file
+fn decrypt_age_key(encrypted: &str) -> anyhow::Result<Vec<u8>> {
+    let identities = match env::var("SOPS_AGE_KEY") {
+        Ok(keys) => age::IdentityFile::from_buffer(keys.as_bytes())?,
+        Err(_) => age::IdentityFile::from_file(
+            env::var("SOPS_AGE_KEY_FILE")
+                .context("SOPS_AGE_KEY or SOPS_AGE_KEY_FILE must be set for age keys")?,
+        )?,
+    }
+    .into_identities()?;
+
+    let decryptor = age::Decryptor::new(age::armor::ArmoredReader::new(encrypted.as_bytes()))?;
+    let mut reader = decryptor.decrypt(identities.iter().map(|identity| identity.as_ref()))?;
+    let mut key = Vec::new();
+    reader.read_to_end(&mut key)?;
+    Ok(key)
+}
+
+async fn decrypt_kms_key(kms_key: &serde_json::Value) -> anyhow::Result<Vec<u8>> {
+    let arn = kms_key["arn"].as_str().context("kms key has no arn")?;
+    let encrypted = base64::engine::general_purpose::STANDARD
+        .decode(kms_key["enc"].as_str().context("kms key has no enc")?)?;
+
+    let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    let mut request = aws_sdk_kms::Client::new(&aws_config)
+        .decrypt()
+        .key_id(arn)
+        .ciphertext_blob(aws_sdk_kms::primitives::Blob::new(encrypted));
+    for (name, value) in kms_key["context"].as_object().into_iter().flatten() {
+        request = request.encryption_context(name, value.as_str().unwrap_or_default());
+    }
+
+    let response = request.send().await?;
+    Ok(response
+        .plaintext()
+        .context("kms decrypt returned no plaintext")?
+        .as_ref()
+        .to_vec())
+}
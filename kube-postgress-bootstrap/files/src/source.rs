@@ -0,0 +1,189 @@
+use std::{collections::BTreeMap, env, io::Read};
+
+use anyhow::Context;
+use aws_sdk_s3::operation::get_object::GetObjectOutput;
+use k8s_openapi::api::core::v1::ConfigMap;
+use tracing::info;
+
+use crate::{cosign, oci};
+
+// reads a file referenced from the config, supporting local paths as well as
+// s3://, https:// and oci:// locations. gzip'd content is decompressed transparently.
+// seeds are read in chunks by seed::open instead.
+pub async fn read_source(location: &str) -> anyhow::Result<String> {
+    decode(location, read_bytes(location).await?)
+}
+
+// reads the config file, verifying its cosign signature when COSIGN_PUBLIC_KEY is
+// set. blob signatures are read from CONFIG_SIGNATURE, defaulting to <location>.sig,
+// oci artifacts are checked against the signatures cosign attaches in the registry.
+pub async fn read_config(location: &str) -> anyhow::Result<String> {
+    read_signed(location, env::var("CONFIG_SIGNATURE").ok()).await
+}
+
+// reads a file of a config directory, each one is signed on its own as <file>.sig
+pub async fn read_config_file(location: &str) -> anyhow::Result<String> {
+    if env::var("CONFIG_SIGNATURE").is_ok() && cosign::public_key().await?.is_some() {
+        anyhow::bail!(
+            "CONFIG_SIGNATURE is the signature of a single file, the files of a directory are signed as <file>.sig"
+        );
+    }
+    read_signed(location, None).await
+}
+
+async fn read_signed(location: &str, signature: Option<String>) -> anyhow::Result<String> {
+    let public_key = match cosign::public_key().await? {
+        Some(public_key) => public_key,
+        None => return read_source(location).await,
+    };
+
+    let bytes = if location.starts_with("oci://") {
+        oci::read_artifact(location, Some(&public_key)).await?
+    } else {
+        let bytes = read_bytes(location).await?;
+        let signature_location = signature.unwrap_or_else(|| format!("{}.sig", location));
+        let signature = read_bytes(&signature_location).await?;
+        cosign::verify(&public_key, &bytes, &signature)
+            .with_context(|| format!("failed to verify signature of {}", location))?;
+        info!("verified cosign signature of {}", location);
+        bytes
+    };
+
+    decode(location, bytes)
+}
+
+async fn read_bytes(location: &str) -> anyhow::Result<Vec<u8>> {
+    if location.starts_with("s3://") {
+        read_s3(location).await
+    } else if location.starts_with("oci://") {
+        oci::read_artifact(location, None).await
+    } else if location.starts_with("https://") || location.starts_with("http://") {
+        Ok(reqwest::get(location)
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?
+            .to_vec())
+    } else {
+        Ok(std::fs::read(location)?)
+    }
+}
+
+fn decode(location: &str, bytes: Vec<u8>) -> anyhow::Result<String> {
+    let bytes = if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(bytes.as_slice())
+            .read_to_end(&mut decompressed)
+            .with_context(|| format!("failed to decompress {}", location))?;
+        decompressed
+    } else {
+        bytes
+    };
+
+    String::from_utf8(bytes).with_context(|| format!("{} is not valid utf-8", location))
+}
+
+pub const CONFIG_MAP_PREFIX: &str = "configmap://";
+
+// the *.json, *.yaml and *.yml files (optionally gzipped) in a local directory, in
+// name order, or None when the location isn't a directory. hidden entries are
+// skipped, like the ..data links of a mounted configmap.
+pub fn config_files(location: &str) -> anyhow::Result<Option<Vec<String>>> {
+    if location.contains("://") || !std::path::Path::new(location).is_dir() {
+        return Ok(None);
+    }
+    let mut files = Vec::new();
+    for dir_entry in std::fs::read_dir(location)? {
+        let path = dir_entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let extension = name.trim_end_matches(".gz");
+        if name.starts_with('.')
+            || !(extension.ends_with(".json")
+                || extension.ends_with(".yaml")
+                || extension.ends_with(".yml"))
+            || !path.is_file()
+        {
+            continue;
+        }
+        files.push(path.to_string_lossy().into_owned());
+    }
+    anyhow::ensure!(!files.is_empty(), "{} has no config files", location);
+    files.sort();
+    Ok(Some(files))
+}
+
+// reads every key of the configmap://namespace/name ConfigMap. with
+// COSIGN_PUBLIC_KEY every key has to be signed by a <key>.sig key next to it, the
+// signature keys aren't config.
+pub async fn read_config_map(
+    kube_client: &kube::Client,
+    location: &str,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let (namespace, name) = split_config_map_location(location)?;
+    let config_maps: kube::api::Api<ConfigMap> =
+        kube::api::Api::namespaced(kube_client.clone(), namespace);
+    let config_map = config_maps.get(name).await?;
+    let (signatures, data): (BTreeMap<String, String>, BTreeMap<String, String>) = config_map
+        .data
+        .unwrap_or_default()
+        .into_iter()
+        .partition(|(key, _)| key.ends_with(".sig"));
+    anyhow::ensure!(!data.is_empty(), "config map {} has no data", location);
+
+    if let Some(public_key) = cosign::public_key().await? {
+        for (key, config) in data.iter() {
+            let signature = signatures
+                .get(&format!("{}.sig", key))
+                .with_context(|| format!("key {} of {} has no {}.sig key", key, location, key))?;
+            cosign::verify(&public_key, config.as_bytes(), signature.as_bytes())
+                .with_context(|| format!("failed to verify signature of {}/{}", location, key))?;
+        }
+        info!("verified cosign signatures of {}", location);
+    }
+    Ok(data)
+}
+
+// splits configmap://namespace/name into its namespace and name
+pub fn split_config_map_location(location: &str) -> anyhow::Result<(&str, &str)> {
+    location
+        .strip_prefix(CONFIG_MAP_PREFIX)
+        .and_then(|path| path.split_once('/'))
+        .with_context(|| {
+            format!(
+                "config map location must be {}namespace/name, got {}",
+                CONFIG_MAP_PREFIX, location
+            )
+        })
+}
+
+// splits s3://bucket/key into its bucket and key
+pub fn split_s3_location(location: &str) -> anyhow::Result<(&str, &str)> {
+    location
+        .strip_prefix("s3://")
+        .and_then(|path| path.split_once('/'))
+        .with_context(|| format!("s3 location must be s3://bucket/key, got {}", location))
+}
+
+pub async fn s3_client() -> aws_sdk_s3::Client {
+    let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    aws_sdk_s3::Client::new(&aws_config)
+}
+
+pub async fn s3_object(location: &str) -> anyhow::Result<GetObjectOutput> {
+    let (bucket, key) = split_s3_location(location)?;
+    Ok(s3_client()
+        .await
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await?)
+}
+
+async fn read_s3(location: &str) -> anyhow::Result<Vec<u8>> {
+    let object = s3_object(location).await?;
+    Ok(object.body.collect().await?.into_bytes().to_vec())
+}
@@ -0,0 +1,57 @@
+use std::{env, time::Duration};
+
+use tracing::warn;
+
+use crate::{timing, uri_host};
+
+const PREFIX: &str = "kube_postgres_bootstrap";
+
+// sends the run's metrics to STATSD_HOST:STATSD_PORT (default 8125) using dogstatsd
+// tags, for clusters that ship metrics through the datadog agent
+pub async fn emit_run_metrics(success: bool, duration: Duration) {
+    let host = match env::var("STATSD_HOST") {
+        Ok(host) => host,
+        Err(_) => return,
+    };
+    let port = env::var("STATSD_PORT").unwrap_or_else(|_| "8125".to_string());
+
+    let mut lines = vec![
+        format!("{}.run.success:{}|g", PREFIX, u8::from(success)),
+        format!("{}.run.duration_ms:{}|g", PREFIX, duration.as_millis()),
+    ];
+    for (operation, timing) in timing::snapshot().iter() {
+        let tag = operation.replace(' ', "_").to_lowercase();
+        lines.push(format!(
+            "{}.operation.count:{}|c|#operation:{}",
+            PREFIX, timing.count, tag
+        ));
+        lines.push(format!(
+            "{}.operation.duration_ms:{}|c|#operation:{}",
+            PREFIX,
+            timing.total.as_millis(),
+            tag
+        ));
+        lines.push(format!(
+            "{}.operation.max_duration_ms:{}|g|#operation:{}",
+            PREFIX,
+            timing.max.as_millis(),
+            tag
+        ));
+    }
+
+    if let Err(e) = send(&host, &port, &lines).await {
+        warn!("failed to send statsd metrics: {}", e);
+    }
+}
+
+// one packet per metric keeps each well under the udp size limit
+async fn send(host: &str, port: &str, lines: &[String]) -> std::io::Result<()> {
+    let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
+    socket
+        .connect(format!("{}:{}", uri_host(host), port))
+        .await?;
+    for line in lines.iter() {
+        socket.send(line.as_bytes()).await?;
+    }
+    Ok(())
+}
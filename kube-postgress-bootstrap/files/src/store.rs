@@ -0,0 +1,413 @@
+use std::{collections::BTreeMap, env, sync::OnceLock};
+
+use anyhow::Context;
+use base64::Engine;
+use tracing::info;
+
+use crate::{vault, ConfigError, MANAGED_BY};
+
+// somewhere the credentials are copied to, for workloads running outside the
+// cluster. set per entry with secretStores or for every entry with SECRET_STORES
+// (the same list as json).
+pub trait SecretStore {
+    // names the store in errors
+    fn describe(&self) -> String;
+    async fn write(
+        &self,
+        namespace: &str,
+        username: &str,
+        data: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()>;
+}
+
+// paths and names take {namespace} and {username}, which is how a store in
+// SECRET_STORES keeps the entries apart
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SecretStoreConfig {
+    Vault(VaultKvStore),
+    Aws(AwsSecretsManagerStore),
+    Gcp(GcpSecretManagerStore),
+}
+
+impl SecretStoreConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            SecretStoreConfig::Gcp(gcp) => {
+                let secret = gcp
+                    .secret
+                    .replace("{namespace}", "")
+                    .replace("{username}", "");
+                if secret
+                    .chars()
+                    .any(|c| !c.is_ascii_alphanumeric() && c != '-' && c != '_')
+                {
+                    return Err(format!(
+                        "gcp secret {} may only contain letters, digits, - and _",
+                        gcp.secret
+                    ));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl SecretStore for SecretStoreConfig {
+    fn describe(&self) -> String {
+        match self {
+            SecretStoreConfig::Vault(store) => store.describe(),
+            SecretStoreConfig::Aws(store) => store.describe(),
+            SecretStoreConfig::Gcp(store) => store.describe(),
+        }
+    }
+
+    async fn write(
+        &self,
+        namespace: &str,
+        username: &str,
+        data: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()> {
+        match self {
+            SecretStoreConfig::Vault(store) => store.write(namespace, username, data).await,
+            SecretStoreConfig::Aws(store) => store.write(namespace, username, data).await,
+            SecretStoreConfig::Gcp(store) => store.write(namespace, username, data).await,
+        }
+    }
+}
+
+fn render(template: &str, namespace: &str, username: &str) -> String {
+    template
+        .replace("{namespace}", namespace)
+        .replace("{username}", username)
+}
+
+fn default_store_path() -> String {
+    "{namespace}/{username}".to_string()
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultKvStore {
+    #[serde(default = "default_vault_mount")]
+    mount: String,
+    #[serde(default = "default_store_path")]
+    path: String,
+}
+
+fn default_vault_mount() -> String {
+    "secret".to_string()
+}
+
+impl SecretStore for VaultKvStore {
+    fn describe(&self) -> String {
+        format!("vault {}/{}", self.mount, self.path)
+    }
+
+    async fn write(
+        &self,
+        namespace: &str,
+        username: &str,
+        data: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()> {
+        write_vault_kv(&self.mount, &render(&self.path, namespace, username), data).await
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AwsSecretsManagerStore {
+    #[serde(default = "default_store_path")]
+    name: String,
+}
+
+impl SecretStore for AwsSecretsManagerStore {
+    fn describe(&self) -> String {
+        format!("aws secret {}", self.name)
+    }
+
+    async fn write(
+        &self,
+        namespace: &str,
+        username: &str,
+        data: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()> {
+        write_aws_secret(&render(&self.name, namespace, username), data).await
+    }
+}
+
+// gcp secret ids can't contain slashes
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GcpSecretManagerStore {
+    project: String,
+    #[serde(default = "default_gcp_secret")]
+    secret: String,
+}
+
+fn default_gcp_secret() -> String {
+    "{namespace}-{username}".to_string()
+}
+
+impl SecretStore for GcpSecretManagerStore {
+    fn describe(&self) -> String {
+        format!("gcp secret {}/{}", self.project, self.secret)
+    }
+
+    async fn write(
+        &self,
+        namespace: &str,
+        username: &str,
+        data: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()> {
+        write_gcp_secret(
+            &self.project,
+            &render(&self.secret, namespace, username),
+            data,
+        )
+        .await
+    }
+}
+
+static GLOBAL_STORES: OnceLock<Vec<SecretStoreConfig>> = OnceLock::new();
+
+// the stores of SECRET_STORES, which every entry is written to
+pub fn global_stores() -> &'static [SecretStoreConfig] {
+    GLOBAL_STORES.get_or_init(Vec::new)
+}
+
+// reads SECRET_STORES, before any entry is reconciled
+pub fn configure() -> Result<(), ConfigError> {
+    let stores = match env::var("SECRET_STORES") {
+        Ok(stores) => {
+            let stores: Vec<SecretStoreConfig> = serde_json::from_str(&stores).map_err(|e| {
+                ConfigError::setting(
+                    "SECRET_STORES",
+                    format!("not a valid list of stores: {}", e),
+                )
+            })?;
+            for store in stores.iter() {
+                store
+                    .validate()
+                    .map_err(|e| ConfigError::setting("SECRET_STORES", e))?;
+            }
+            stores
+        }
+        Err(_) => Vec::new(),
+    };
+    let _ = GLOBAL_STORES.set(stores);
+    Ok(())
+}
+
+// writes the credentials to a vault kv v2 secret engine mounted at mount
+pub async fn write_vault_kv(
+    mount: &str,
+    path: &str,
+    data: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    vault::write_kv(mount, path, &serde_json::json!({ "data": data })).await
+}
+
+// writes the credentials as a json secret string to aws secrets manager,
+// creating the secret on first use
+pub async fn write_aws_secret(name: &str, data: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    let client = aws_sdk_secretsmanager::Client::new(&aws_config);
+    let secret_string = serde_json::to_string(data)?;
+
+    match client
+        .put_secret_value()
+        .secret_id(name)
+        .secret_string(&secret_string)
+        .send()
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e)
+            if e.as_service_error()
+                .is_some_and(|e| e.is_resource_not_found_exception()) =>
+        {
+            client
+                .create_secret()
+                .name(name)
+                .secret_string(secret_string)
+                .send()
+                .await
+                .with_context(|| format!("failed to create aws secret {}", name))?;
+            Ok(())
+        }
+        Err(e) => Err(e).with_context(|| format!("failed to write aws secret {}", name)),
+    }
+}
+
+// adds the credentials as a new version of a gcp secret manager secret, holding
+// them as json like for aws and creating the secret on first use. the access
+// token is GOOGLE_OAUTH_ACCESS_TOKEN or the one of the metadata server, i.e.
+// the workload identity of the pod.
+pub async fn write_gcp_secret(
+    project: &str,
+    secret: &str,
+    data: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    let token = gcp_access_token(&client).await?;
+    let secret_url = format!(
+        "https://secretmanager.googleapis.com/v1/projects/{}/secrets/{}",
+        project, secret
+    );
+    let version = serde_json::json!({
+        "payload": {
+            "data": base64::engine::general_purpose::STANDARD.encode(serde_json::to_string(data)?),
+        },
+    });
+
+    let response = client
+        .post(format!("{}:addVersion", secret_url))
+        .bearer_auth(&token)
+        .json(&version)
+        .send()
+        .await?;
+    if response.status() != reqwest::StatusCode::NOT_FOUND {
+        response
+            .error_for_status()
+            .with_context(|| format!("failed to write gcp secret {}", secret))?;
+        return Ok(());
+    }
+
+    client
+        .post(format!(
+            "https://secretmanager.googleapis.com/v1/projects/{}/secrets",
+            project
+        ))
+        .bearer_auth(&token)
+        .query(&[("secretId", secret)])
+        .json(&serde_json::json!({
+            "replication": { "automatic": {} },
+            "labels": { "managed-by": MANAGED_BY },
+        }))
+        .send()
+        .await?
+        .error_for_status()
+        .with_context(|| format!("failed to create gcp secret {}", secret))?;
+    client
+        .post(format!("{}:addVersion", secret_url))
+        .bearer_auth(&token)
+        .json(&version)
+        .send()
+        .await?
+        .error_for_status()
+        .with_context(|| format!("failed to write gcp secret {}", secret))?;
+    Ok(())
+}
+
+async fn gcp_access_token(client: &reqwest::Client) -> anyhow::Result<String> {
+    if let Ok(token) = env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+        return Ok(token);
+    }
+    let token: serde_json::Value = client
+        .get("http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token")
+        .header("Metadata-Flavor", "Google")
+        .send()
+        .await
+        .context("failed to reach the gcp metadata server, set GOOGLE_OAUTH_ACCESS_TOKEN outside gcp")?
+        .error_for_status()?
+        .json()
+        .await?;
+    token["access_token"]
+        .as_str()
+        .map(str::to_string)
+        .context("the gcp metadata server returned no access token")
+}
+
+// creates or updates a 1password item (matched by title) through the connect api
+pub async fn write_onepassword_item(
+    vault: &str,
+    title: &str,
+    data: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let host =
+        env::var("OP_CONNECT_HOST").context("OP_CONNECT_HOST must be set to write to 1password")?;
+    let token = env::var("OP_CONNECT_TOKEN")
+        .context("OP_CONNECT_TOKEN must be set to write to 1password")?;
+    let items_url = format!("{}/v1/vaults/{}/items", host.trim_end_matches('/'), vault);
+    let client = reqwest::Client::new();
+
+    let existing: Vec<serde_json::Value> = client
+        .get(&items_url)
+        .bearer_auth(&token)
+        .query(&[("filter", format!("title eq \"{}\"", filter_string(title)))])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let fields: Vec<serde_json::Value> = data
+        .iter()
+        .map(|(key, value)| {
+            let field_type = if concealed(key) {
+                "CONCEALED"
+            } else {
+                "STRING"
+            };
+            serde_json::json!({ "label": key, "type": field_type, "value": value })
+        })
+        .collect();
+    let mut item = serde_json::json!({
+        "vault": { "id": vault },
+        "title": title,
+        "category": "DATABASE",
+        "tags": [MANAGED_BY],
+        "fields": fields,
+    });
+
+    let request = match existing.first().and_then(|item| item["id"].as_str()) {
+        Some(id) => {
+            item["id"] = serde_json::Value::String(id.to_string());
+            client.put(format!("{}/{}", items_url, id))
+        }
+        None => client.post(&items_url),
+    };
+    request
+        .bearer_auth(&token)
+        .json(&item)
+        .send()
+        .await?
+        .error_for_status()
+        .with_context(|| format!("failed to write 1password item {}", title))?;
+
+    info!("successfully wrote 1password item: {}", title);
+    Ok(())
+}
+
+// only the default keys that never hold the password are shown in the clear,
+// connection strings and the keys of a secretTemplate can embed it
+fn concealed(key: &str) -> bool {
+    !matches!(key, "database_host" | "database_port" | "username") && !key.starts_with("database.")
+}
+
+// a quoted string of a connect api filter, entry titles can hold any character
+fn filter_string(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_strings_are_concealed() {
+        assert!(!concealed("database_host"));
+        assert!(!concealed("database.0"));
+        assert!(concealed("password"));
+        assert!(concealed("database_url.0"));
+        assert!(concealed("database_uri.0"));
+        assert!(concealed("DATABASE_URL"));
+    }
+
+    #[test]
+    fn filter_strings_are_escaped() {
+        assert_eq!(filter_string(r#"team/"app" \ db"#), r#"team/\"app\" \\ db"#);
+    }
+}
@@ -0,0 +1,91 @@
+use std::{future::Future, sync::OnceLock, time::Duration};
+
+use tracing::warn;
+
+struct Settings {
+    operation: Option<Duration>,
+    statement_seconds: u64,
+    lock_seconds: u64,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+// set from --operation-timeout-seconds, --statement-timeout-seconds and
+// --lock-timeout-seconds before anything connects, 0 turns a timeout off
+pub fn configure(operation_seconds: u64, statement_seconds: u64, lock_seconds: u64) {
+    let _ = SETTINGS.set(Settings {
+        operation: (operation_seconds > 0).then(|| Duration::from_secs(operation_seconds)),
+        statement_seconds,
+        lock_seconds,
+    });
+}
+
+fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| Settings {
+        operation: None,
+        statement_seconds: 0,
+        lock_seconds: 0,
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{operation} did not finish within {seconds}s")]
+pub struct OperationTimeout {
+    pub operation: String,
+    pub seconds: u64,
+}
+
+// runs a single sql statement or api call, failing it once it takes longer than
+// the operation timeout. the caller gets the operation back in the error.
+pub async fn limit<T>(
+    operation: &str,
+    future: impl Future<Output = T>,
+) -> Result<T, OperationTimeout> {
+    let timeout = match settings().operation {
+        Some(timeout) => timeout,
+        None => return Ok(future.await),
+    };
+    tokio::time::timeout(timeout, future).await.map_err(|_| {
+        warn!(
+            "{} did not finish within {}s, giving up on it",
+            operation,
+            timeout.as_secs()
+        );
+        OperationTimeout {
+            operation: operation.to_string(),
+            seconds: timeout.as_secs(),
+        }
+    })
+}
+
+// run on every postgres session, so a statement stuck behind another session's
+// lock fails on the server with a clear error well before the operation timeout.
+// lock_timeout errors are retried like other lock conflicts.
+pub fn postgres_session_settings() -> Option<String> {
+    let settings = settings();
+    let mut statements = Vec::new();
+    if settings.statement_seconds > 0 {
+        statements.push(format!(
+            "SET statement_timeout = '{}s'",
+            settings.statement_seconds
+        ));
+    }
+    if settings.lock_seconds > 0 {
+        statements.push(format!("SET lock_timeout = '{}s'", settings.lock_seconds));
+    }
+    (!statements.is_empty()).then(|| statements.join("; "))
+}
+
+// the same for mysql sessions, which only have a timeout for metadata locks
+// (e.g. an ALTER waiting on a long transaction)
+pub fn mysql_session_settings() -> Vec<String> {
+    let settings = settings();
+    if settings.lock_seconds > 0 {
+        vec![format!(
+            "SET SESSION lock_wait_timeout = {}",
+            settings.lock_seconds
+        )]
+    } else {
+        Vec::new()
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, OnceLock},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use k8s_openapi::http::Request;
+use tokio_postgres::{types::ToSql, GenericClient, Row};
+use tracing::{debug, info, warn};
+
+use crate::{
+    concurrency, env_number,
+    retry::{retry, transient_query},
+    timeouts, tls, ConfigError, SqlError,
+};
+
+// durations of every sql statement and kubernetes api call, grouped by operation
+// (e.g. "sql CREATE DATABASE" or "kube POST secrets")
+static TIMINGS: Mutex<BTreeMap<String, Timing>> = Mutex::new(BTreeMap::new());
+
+#[derive(Clone, Default)]
+pub struct Timing {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+static SLOW_THRESHOLD: OnceLock<Duration> = OnceLock::new();
+
+// operations slower than SLOW_OPERATION_THRESHOLD_MS (default 1000) log a warning,
+// read before anything runs
+pub fn configure() -> Result<(), ConfigError> {
+    let ms = env_number("SLOW_OPERATION_THRESHOLD_MS")?.unwrap_or(1000);
+    let _ = SLOW_THRESHOLD.set(Duration::from_millis(ms));
+    Ok(())
+}
+
+fn slow_threshold() -> Duration {
+    SLOW_THRESHOLD
+        .get()
+        .copied()
+        .unwrap_or(Duration::from_millis(1000))
+}
+
+pub fn record(operation: String, detail: &str, elapsed: Duration) {
+    if elapsed > slow_threshold() {
+        warn!(
+            operation = %operation,
+            elapsed_ms = elapsed.as_millis() as u64,
+            "slow {} took {}ms: {}",
+            operation,
+            elapsed.as_millis(),
+            detail
+        );
+    } else {
+        debug!(
+            operation = %operation,
+            elapsed_ms = elapsed.as_millis() as u64,
+            "{} took {}ms",
+            operation,
+            elapsed.as_millis()
+        );
+    }
+
+    let mut timings = TIMINGS.lock().unwrap();
+    let timing = timings.entry(operation).or_default();
+    timing.count += 1;
+    timing.total += elapsed;
+    timing.max = timing.max.max(elapsed);
+}
+
+pub fn snapshot() -> BTreeMap<String, Timing> {
+    TIMINGS.lock().unwrap().clone()
+}
+
+pub fn print_summary() {
+    let timings = snapshot();
+    if timings.is_empty() {
+        return;
+    }
+
+    info!("timing summary:");
+    for (operation, timing) in timings.iter() {
+        info!(
+            operation = %operation,
+            count = timing.count,
+            total_ms = timing.total.as_millis() as u64,
+            max_ms = timing.max.as_millis() as u64,
+            "  {}: count={} total={}ms max={}ms",
+            operation,
+            timing.count,
+            timing.total.as_millis(),
+            timing.max.as_millis()
+        );
+    }
+}
+
+// groups statements by their leading keywords, names stay out of the operation
+pub fn sql_operation(statement: &str) -> String {
+    let keywords: Vec<&str> = statement
+        .split_whitespace()
+        .take(2)
+        .take_while(|word| word.chars().all(|c| c.is_ascii_uppercase()))
+        .collect();
+    format!("sql {}", keywords.join(" "))
+}
+
+pub async fn timed_sql<T>(statement: &str, query: impl std::future::Future<Output = T>) -> T {
+    let start = Instant::now();
+    let result = query.await;
+    record(sql_operation(statement), statement, start.elapsed());
+    result
+}
+
+// a statement running into the operation timeout is cancelled on the server, so
+// the statements queued behind it on the session can run. with parallelism the
+// admin session is shared, cancelling would hit the statements of other entries.
+async fn bounded<T>(
+    client: &tokio_postgres::Client,
+    statement: &str,
+    query: impl std::future::Future<Output = Result<T, tokio_postgres::Error>>,
+) -> Result<T, SqlError> {
+    let operation = sql_operation(statement);
+    match timed_sql(statement, timeouts::limit(&operation, query)).await {
+        Ok(result) => Ok(result?),
+        Err(timeout) => {
+            if concurrency::parallelism() == 1 {
+                if let Err(e) = client.cancel_token().cancel_query(tls::connector()).await {
+                    warn!("failed to cancel {}: {}", operation, e);
+                }
+            }
+            Err(timeout.into())
+        }
+    }
+}
+
+// timed variants of the client methods, usable on clients and transactions.
+// statements conflicting with concurrent transactions are retried, each attempt
+// is bounded by the operation timeout.
+pub trait TimedClient {
+    async fn timed_execute(
+        &self,
+        statement: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<u64, SqlError>;
+    async fn timed_query(
+        &self,
+        statement: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<Row>, SqlError>;
+    async fn timed_query_one(
+        &self,
+        statement: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Row, SqlError>;
+}
+
+impl<C: GenericClient + Sync> TimedClient for C {
+    async fn timed_execute(
+        &self,
+        statement: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<u64, SqlError> {
+        retry(&sql_operation(statement), transient_query, || {
+            bounded(self.client(), statement, self.execute(statement, params))
+        })
+        .await
+    }
+
+    async fn timed_query(
+        &self,
+        statement: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<Row>, SqlError> {
+        retry(&sql_operation(statement), transient_query, || {
+            bounded(self.client(), statement, self.query(statement, params))
+        })
+        .await
+    }
+
+    async fn timed_query_one(
+        &self,
+        statement: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Row, SqlError> {
+        retry(&sql_operation(statement), transient_query, || {
+            bounded(self.client(), statement, self.query_one(statement, params))
+        })
+        .await
+    }
+}
+
+// kube client middleware timing every api request, and failing the ones that run
+// into the operation timeout. watches are long polls and aren't bounded.
+pub struct KubeTimingLayer;
+
+impl<S> tower::Layer<S> for KubeTimingLayer {
+    type Service = KubeTiming<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        KubeTiming { inner }
+    }
+}
+
+pub struct KubeTiming<S> {
+    inner: S,
+}
+
+impl<S, B> tower::Service<Request<B>> for KubeTiming<S>
+where
+    S: tower::Service<Request<B>>,
+    S::Future: Send + 'static,
+    S::Error: Into<tower::BoxError>,
+{
+    type Response = S::Response;
+    type Error = tower::BoxError;
+    type Future = BoxFuture<'static, Result<S::Response, tower::BoxError>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), tower::BoxError>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let operation = format!(
+            "kube {} {}",
+            request.method(),
+            kube_resource(request.uri().path())
+        );
+        let detail = format!("{} {}", request.method(), request.uri().path());
+        let watch = request
+            .uri()
+            .query()
+            .is_some_and(|query| query.split('&').any(|param| param == "watch=true"));
+        let start = Instant::now();
+        let response = self.inner.call(request);
+
+        async move {
+            let response = if watch {
+                response.await
+            } else {
+                timeouts::limit(&operation, response).await?
+            };
+            record(operation, &detail, start.elapsed());
+            response.map_err(Into::into)
+        }
+        .boxed()
+    }
+}
+
+// the resource type of an api path, e.g. /api/v1/namespaces/default/secrets/foo
+// is secrets
+fn kube_resource(path: &str) -> String {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let rest = match segments.first() {
+        Some(&"api") => segments.get(2..),
+        Some(&"apis") => segments.get(3..),
+        _ => None,
+    }
+    .unwrap_or_default();
+
+    match rest {
+        ["namespaces", _, resource, ..] => resource.to_string(),
+        [resource, ..] => resource.to_string(),
+        [] => path.to_string(),
+    }
+}
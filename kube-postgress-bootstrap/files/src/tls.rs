@@ -0,0 +1,297 @@
+use std::{
+    env,
+    sync::{Arc, OnceLock},
+};
+
+use anyhow::Context;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_postgres_rustls::MakeRustlsConnect;
+use tokio_rustls::rustls::{
+    self,
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    CertificateError, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use tracing::warn;
+
+use crate::{env_number, uri_host, ConfigError, SSL_MODES};
+
+static CONNECTOR: OnceLock<MakeRustlsConnect> = OnceLock::new();
+static WARNING_DAYS: OnceLock<i64> = OnceLock::new();
+
+// DB_SSLMODE works like libpq's sslmode: disable (the default), prefer and require
+// encrypt without checking the server, verify-ca checks the certificate against
+// DB_SSLROOTCERT (or the system roots) and verify-full also checks the hostname.
+// DB_SSLCERT and DB_SSLKEY provide a client certificate.
+pub fn ssl_mode() -> String {
+    env::var("DB_SSLMODE").unwrap_or_else(|_| "disable".to_string())
+}
+
+// checks DB_SSLMODE and DB_CERT_EXPIRY_WARNING_DAYS and sets up the connector,
+// before anything connects
+pub fn configure() -> Result<(), ConfigError> {
+    let mode = ssl_mode();
+    if !SSL_MODES.contains(&mode.as_str()) {
+        return Err(ConfigError::setting(
+            "DB_SSLMODE",
+            format!("{:?} is not an sslmode", mode),
+        ));
+    }
+    let _ = WARNING_DAYS.set(env_number("DB_CERT_EXPIRY_WARNING_DAYS")?.unwrap_or(14));
+    let config =
+        client_config().map_err(|e| ConfigError::setting("database tls", format!("{:#}", e)))?;
+    let _ = CONNECTOR.set(MakeRustlsConnect::new(config));
+    Ok(())
+}
+
+// the sslmode for connection strings, tokio-postgres only knows whether tls is
+// used, verification is done by the connector
+pub fn connection_ssl_mode() -> &'static str {
+    match ssl_mode().as_str() {
+        "require" | "verify-ca" | "verify-full" => "require",
+        "allow" | "prefer" => "prefer",
+        // other modes are rejected by configure
+        _ => "disable",
+    }
+}
+
+pub fn connector() -> MakeRustlsConnect {
+    CONNECTOR
+        .get_or_init(|| {
+            MakeRustlsConnect::new(client_config().expect("failed to configure database tls"))
+        })
+        .clone()
+}
+
+fn client_config() -> anyhow::Result<rustls::ClientConfig> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match ssl_mode().as_str() {
+        "verify-ca" | "verify-full" => {
+            let verifier = WebPkiServerVerifier::builder_with_provider(
+                Arc::new(root_certificates()?),
+                provider,
+            )
+            .build()?;
+            if ssl_mode() == "verify-full" {
+                builder.with_webpki_verifier(verifier)
+            } else {
+                builder
+                    .dangerous()
+                    .with_custom_certificate_verifier(Arc::new(IgnoreHostname(verifier)))
+            }
+        }
+        _ => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider))),
+    };
+
+    match (env::var("DB_SSLCERT"), env::var("DB_SSLKEY")) {
+        (Ok(cert), Ok(key)) => {
+            let chain = CertificateDer::pem_file_iter(&cert)
+                .with_context(|| format!("failed to read DB_SSLCERT {}", cert))?
+                .collect::<Result<Vec<_>, _>>()?;
+            let key = PrivateKeyDer::from_pem_file(&key)
+                .with_context(|| format!("failed to read DB_SSLKEY {}", key))?;
+            Ok(builder.with_client_auth_cert(chain, key)?)
+        }
+        (Err(_), Err(_)) => Ok(builder.with_no_client_auth()),
+        _ => anyhow::bail!("DB_SSLCERT and DB_SSLKEY must be set together"),
+    }
+}
+
+fn root_certificates() -> anyhow::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    match env::var("DB_SSLROOTCERT") {
+        Ok(path) => {
+            for certificate in CertificateDer::pem_file_iter(&path)
+                .with_context(|| format!("failed to read DB_SSLROOTCERT {}", path))?
+            {
+                roots.add(certificate?)?;
+            }
+        }
+        Err(_) => {
+            let native = rustls_native_certs::load_native_certs();
+            roots.add_parsable_certificates(native.certs);
+        }
+    }
+    Ok(roots)
+}
+
+// the postgres SSLRequest message, sent before the tls handshake
+const SSL_REQUEST: [u8; 8] = [0, 0, 0, 8, 4, 210, 22, 47];
+
+// warns when a certificate in the server's chain expires within
+// DB_CERT_EXPIRY_WARNING_DAYS (default 14), an expired certificate otherwise only
+// shows up as an opaque connection error. servers without tls are skipped, and
+// with DB_SSLMODE=disable the server isn't asked for its certificate at all.
+pub async fn check_certificate_expiry(host: &str, port: &str) -> anyhow::Result<()> {
+    if ssl_mode() == "disable" {
+        return Ok(());
+    }
+    let warning_days = WARNING_DAYS.get().copied().unwrap_or(14);
+
+    let chain = match server_certificates(host, port).await? {
+        Some(chain) => chain,
+        None => return Ok(()),
+    };
+
+    let now = x509_parser::time::ASN1Time::now();
+    for certificate in chain.iter() {
+        let (_, certificate) = x509_parser::parse_x509_certificate(certificate)
+            .map_err(|e| anyhow::anyhow!("failed to parse server certificate: {}", e))?;
+        let not_after = certificate.validity().not_after;
+        let days_left = (not_after.timestamp() - now.timestamp()) / 86400;
+
+        if days_left < 0 {
+            warn!(
+                "server certificate {} expired on {}",
+                certificate.subject(),
+                not_after
+            );
+        } else if days_left <= warning_days {
+            warn!(
+                "server certificate {} expires in {} days on {}",
+                certificate.subject(),
+                days_left,
+                not_after
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// fetches the certificate chain without verifying it, verification is up to the
+// actual connection
+async fn server_certificates(
+    host: &str,
+    port: &str,
+) -> anyhow::Result<Option<Vec<CertificateDer<'static>>>> {
+    let mut stream = tokio::net::TcpStream::connect(format!("{}:{}", uri_host(host), port))
+        .await
+        .with_context(|| format!("failed to connect to {}:{}", host, port))?;
+    stream.write_all(&SSL_REQUEST).await?;
+    if stream.read_u8().await? != b'S' {
+        return Ok(None);
+    }
+
+    let provider = Arc::new(ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+        .with_no_client_auth();
+    let server_name = ServerName::try_from(host.to_string())?;
+    let tls = tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect(server_name, stream)
+        .await?;
+
+    Ok(tls.get_ref().1.peer_certificates().map(|chain| {
+        chain
+            .iter()
+            .map(|certificate| certificate.clone().into_owned())
+            .collect()
+    }))
+}
+
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+// verify-ca only checks that the certificate chains to a trusted root
+#[derive(Debug)]
+struct IgnoreHostname(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for IgnoreHostname {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match self
+            .0
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            result => result,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
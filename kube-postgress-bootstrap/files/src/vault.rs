@@ -0,0 +1,78 @@
+use std::env;
+
+use anyhow::Context;
+
+const SERVICE_ACCOUNT_TOKEN_PATH: &str = "/var/run/secrets/kubernetes.io/serviceaccount/token";
+
+fn address() -> anyhow::Result<String> {
+    Ok(env::var("VAULT_ADDR")
+        .context("VAULT_ADDR must be set to use vault")?
+        .trim_end_matches('/')
+        .to_string())
+}
+
+// VAULT_TOKEN if set, otherwise logs in with the pod's service account through the
+// kubernetes auth method using VAULT_K8S_ROLE
+pub async fn token() -> anyhow::Result<String> {
+    if let Ok(token) = env::var("VAULT_TOKEN") {
+        return Ok(token);
+    }
+
+    let role = env::var("VAULT_K8S_ROLE")
+        .context("either VAULT_TOKEN or VAULT_K8S_ROLE must be set to use vault")?;
+    let mount = env::var("VAULT_K8S_MOUNT").unwrap_or_else(|_| "kubernetes".to_string());
+    let jwt = std::fs::read_to_string(SERVICE_ACCOUNT_TOKEN_PATH)
+        .context("failed to read service account token for vault login")?;
+
+    let response: serde_json::Value = reqwest::Client::new()
+        .post(format!("{}/v1/auth/{}/login", address()?, mount))
+        .json(&serde_json::json!({ "role": role, "jwt": jwt.trim() }))
+        .send()
+        .await?
+        .error_for_status()
+        .context("vault kubernetes login failed")?
+        .json()
+        .await?;
+
+    response["auth"]["client_token"]
+        .as_str()
+        .map(str::to_string)
+        .context("vault login response has no client token")
+}
+
+// reads a vault path, returning the secret data. kv v2 responses nest it one level
+// deeper than kv v1 and the database secrets engine.
+pub async fn read(path: &str) -> anyhow::Result<serde_json::Value> {
+    let response: serde_json::Value = reqwest::Client::new()
+        .get(format!(
+            "{}/v1/{}",
+            address()?,
+            path.trim_start_matches('/')
+        ))
+        .header("X-Vault-Token", token().await?)
+        .send()
+        .await?
+        .error_for_status()
+        .with_context(|| format!("failed to read vault path {}", path))?
+        .json()
+        .await?;
+
+    let data = &response["data"];
+    match data.get("data") {
+        Some(data) if data.is_object() => Ok(data.clone()),
+        _ => Ok(data.clone()),
+    }
+}
+
+pub async fn write_kv(mount: &str, path: &str, body: &serde_json::Value) -> anyhow::Result<()> {
+    reqwest::Client::new()
+        .post(format!("{}/v1/{}/data/{}", address()?, mount, path))
+        .header("X-Vault-Token", token().await?)
+        .json(body)
+        .send()
+        .await?
+        .error_for_status()
+        .with_context(|| format!("failed to write vault secret {}/{}", mount, path))?;
+
+    Ok(())
+}
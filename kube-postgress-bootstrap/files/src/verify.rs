@@ -0,0 +1,281 @@
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::{Api, Patch, PatchParams},
+    error::ErrorResponse,
+};
+use tokio_postgres::error::SqlState;
+use tracing::{info, warn};
+
+use crate::{
+    create_user_if_missing, metrics,
+    provisioner::Engine,
+    quote_identifier, quote_literal, rds, reconcile_entry, render_secret_data,
+    report::Report,
+    retry::{retry, transient_kube},
+    secret_name, secret_value, setup_account_for_config, setup_database,
+    timing::TimedClient,
+    try_connect, DBClients, DBConnection, DatabaseConfig, Error, ServerConnections,
+};
+
+pub enum AuthStatus {
+    Ok,
+    MissingSecret,
+    // the password in the secret is rejected by the database
+    Drifted { username: String, password: String },
+    Error(String),
+}
+
+// logs in with the credentials stored in the entry's secret to each of its
+// databases and runs a query, on the host and port of the secret when it has them
+pub async fn check_auth(
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    db_config: &DatabaseConfig,
+) -> AuthStatus {
+    let secrets: Api<Secret> = Api::namespaced(kube_client.clone(), &db_config.namespace);
+    let secret_name = secret_name(db_config);
+    let secret = match retry(&format!("secret {}", secret_name), transient_kube, || {
+        secrets.get(&secret_name)
+    })
+    .await
+    {
+        Ok(secret) => secret,
+        Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => return AuthStatus::MissingSecret,
+        Err(e) => return AuthStatus::Error(e.to_string()),
+    };
+    let host = secret_value(&secret, "database_host")
+        .unwrap_or_else(|| db_connection_details.host.clone());
+    let port = secret_value(&secret, "database_port")
+        .unwrap_or_else(|| db_connection_details.port.clone());
+    // iamAuth users are checked with a token of their own
+    let password = match secret_value(&secret, &db_config.password_key()) {
+        _ if db_config.iam_auth => match rds::auth_token(
+            host.split(',').next().unwrap(),
+            port.split(',').next().unwrap(),
+            &db_config.username,
+        )
+        .await
+        {
+            Ok(token) => token,
+            Err(e) => return AuthStatus::Error(format!("{:#}", e)),
+        },
+        Some(password) => password,
+        None => return AuthStatus::Error("secret has no password key".to_string()),
+    };
+
+    let user_connection_details = DBConnection {
+        host,
+        port,
+        username: secret_value(&secret, "username").unwrap_or(db_config.username.clone()),
+        password: password.clone(),
+    };
+    let databases = match db_config.databases.is_empty() {
+        true => vec!["postgres".to_string()],
+        false => db_config.databases.clone(),
+    };
+
+    for database in databases.iter() {
+        let client = match try_connect(
+            &user_connection_details.database_connection_string(database),
+        )
+        .await
+        {
+            Ok(client) => client,
+            // a rejected token isn't a password that could be reset
+            Err(e)
+                if !db_config.iam_auth
+                    && (e.code() == Some(&SqlState::INVALID_PASSWORD)
+                        || e.code() == Some(&SqlState::INVALID_AUTHORIZATION_SPECIFICATION)) =>
+            {
+                return AuthStatus::Drifted {
+                    username: user_connection_details.username,
+                    password,
+                }
+            }
+            Err(e) => return AuthStatus::Error(format!("database {}: {}", database, e)),
+        };
+        if let Err(e) = client.timed_query("SELECT 1", &[]).await {
+            return AuthStatus::Error(format!("query in database {} failed: {}", database, e));
+        }
+    }
+    AuthStatus::Ok
+}
+
+// checks every entry and fails if any secret no longer works against the database.
+// with repair_auth drifted users get their password reset to the one in the
+// secret, so consumers keep working without picking up a new credential.
+pub async fn verify(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    db_configs: &[DatabaseConfig],
+    repair_auth: bool,
+) -> anyhow::Result<()> {
+    let mut report = Report::default();
+    let servers = ServerConnections::default();
+    for db_config in db_configs.iter() {
+        let entry = format!("{}/{}", db_config.namespace, db_config.username);
+        if db_config.engine != Engine::Postgres {
+            report.skip(
+                entry,
+                format!(
+                    "a {} entry, only postgres is verified",
+                    db_config.engine.as_str()
+                ),
+            );
+            continue;
+        }
+        if !db_config.create_secret() || db_config.csi.is_some() {
+            report.skip(entry, "its credentials aren't in a secret".to_string());
+            continue;
+        }
+        let result = match check_auth(kube_client, db_connection_details, db_config).await {
+            AuthStatus::Ok => Ok(()),
+            AuthStatus::MissingSecret => {
+                report.skip(entry, "it has no secret yet".to_string());
+                continue;
+            }
+            // on the entry's server, holding its lock like any other run on it
+            AuthStatus::Drifted { .. } if repair_auth => {
+                let (entry, result) = reconcile_entry(
+                    clients,
+                    kube_client,
+                    db_connection_details,
+                    &servers,
+                    "repair-auth",
+                    db_config.clone(),
+                )
+                .await;
+                report.record(entry, result);
+                continue;
+            }
+            AuthStatus::Drifted { .. } => Err(Error::Other(anyhow::anyhow!(
+                "drifted, the password in the secret is rejected by the database"
+            ))),
+            AuthStatus::Error(e) => Err(Error::Other(anyhow::anyhow!(
+                "could not be verified: {}",
+                e
+            ))),
+        };
+        report.record(entry, Ok(result));
+    }
+
+    // the summary is also written to SUMMARY_FILE, e.g. for a ci check
+    Ok(report.finish()?)
+}
+
+// resets the password of a user whose secret no longer works to the one in the
+// secret
+pub async fn repair_auth(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    db_config: &DatabaseConfig,
+) -> Result<(), Error> {
+    match check_auth(kube_client, db_connection_details, db_config).await {
+        AuthStatus::Drifted { username, password } => {
+            warn!(
+                "drifted: resetting password of user {} to the one in the secret",
+                username
+            );
+            clients
+                .admin
+                .timed_execute(
+                    format!(
+                        "ALTER USER {} WITH PASSWORD {};",
+                        quote_identifier(&username),
+                        quote_literal(&password)
+                    )
+                    .as_str(),
+                    &[],
+                )
+                .await
+                .map_err(Error::db(&username, "resetting password"))?;
+        }
+        AuthStatus::Error(e) => warn!(
+            "could not verify the credentials of user {}: {}",
+            db_config.username, e
+        ),
+        AuthStatus::Ok | AuthStatus::MissingSecret => {}
+    }
+    Ok(())
+}
+
+// brings an entry whose secret already exists back in line with the config: a
+// missing user is recreated and a drifted password reset to the one in the secret,
+// missing databases and grants are recreated and the secret's other keys
+// re-rendered. entries without a secret are set up as usual.
+pub async fn repair_drift(
+    clients: &DBClients,
+    kube_client: &kube::Client,
+    db_connection_details: &DBConnection,
+    db_config: DatabaseConfig,
+) -> Result<(), Error> {
+    // iamAuth users have no password to drift
+    if !db_config.create_secret()
+        || !db_config.manage_database()
+        || db_config.csi.is_some()
+        || db_config.iam_auth
+    {
+        return setup_account_for_config(clients, kube_client, db_connection_details, db_config)
+            .await;
+    }
+
+    let username = db_config.username.as_str();
+    let secret_name = secret_name(&db_config);
+    let secret_resource = format!("secret {}/{}", db_config.namespace, secret_name);
+    let secrets: Api<Secret> = Api::namespaced(kube_client.clone(), &db_config.namespace);
+    let secret = match retry(&secret_resource, transient_kube, || {
+        secrets.get(&secret_name)
+    })
+    .await
+    {
+        Ok(secret) => secret,
+        Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => {
+            return setup_account_for_config(clients, kube_client, db_connection_details, db_config)
+                .await
+        }
+        Err(e) => return Err(Error::kube(secret_resource)(e)),
+    };
+    let password = secret_value(&secret, &db_config.password_key())
+        .ok_or_else(|| Error::invalid(username, "secret has no password key".to_string()))?;
+
+    if create_user_if_missing(&clients.admin, username, &password)
+        .await
+        .map_err(Error::db(username, "creating user"))?
+    {
+        metrics::user_created();
+        warn!(
+            "drifted: user {} was missing, recreated it with the password from the secret",
+            username
+        );
+    }
+
+    // creates missing databases and grants the user access again
+    for db in db_config
+        .databases
+        .iter()
+        .filter(|_| db_config.provisions_databases())
+    {
+        setup_database(clients, username, db, db_config.database_options.get(db))
+            .await
+            .map_err(Error::db(username, &format!("repairing database {}", db)))?;
+    }
+
+    repair_auth(clients, kube_client, db_connection_details, &db_config).await?;
+
+    // a merge patch only touches keys the bootstrap renders, e.g. database.N after
+    // databases were added
+    let secret_data =
+        render_secret_data(kube_client, db_connection_details, &db_config, password).await?;
+    let patch_params = PatchParams::default();
+    let patch = Patch::Merge(serde_json::json!({ "stringData": secret_data }));
+    retry(&secret_resource, transient_kube, || {
+        secrets.patch(&secret_name, &patch_params, &patch)
+    })
+    .await
+    .map_err(Error::kube(secret_resource))?;
+
+    info!("reconciled user {} with secret {}", username, secret_name);
+    Ok(())
+}